@@ -0,0 +1,137 @@
+//checks every character file before the game tries to use it
+//usage: cargo run --bin validate-characters [characters folder]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use smash::components::*;
+
+const CHARACTERS_PATH: &str = "assets/characters";
+
+
+fn main() -> ExitCode {
+    let folder = std::env::args().nth(1).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(CHARACTERS_PATH));
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(&folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+            .collect(),
+        Err(err) => {
+            eprintln!("{}: {err}", folder.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    files.sort();
+
+    let mut failed = 0;
+    for file in files.iter() {
+        let errors = validate_file(file);
+        if errors.is_empty() {
+            println!("{}: ok", file.display());
+        } else {
+            failed += 1;
+            for error in errors {
+                eprintln!("{}: {error}", file.display());
+            }
+        }
+    }
+
+    println!("{} characters checked, {failed} with errors", files.len());
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn validate_file(path: &Path) -> Vec<String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => return vec![err.to_string()],
+    };
+    //the position of the error is part of the message
+    let character: Character = match ron::de::from_str(&text) {
+        Ok(character) => character,
+        Err(err) => return vec![err.to_string()],
+    };
+
+    let mut errors = Vec::new();
+    if character.health == 0 {
+        errors.push("health: must be greater than 0".to_string());
+    }
+    for (field, animation) in animations(&character) {
+        validate_animation(&field, animation, &mut errors);
+    }
+    errors
+}
+
+//every animation of the character with the field it comes from
+fn animations(character: &Character) -> Vec<(String, &Animation)> {
+    let mut animations = vec![
+        ("idle".to_string(), &character.idle),
+        ("block".to_string(), &character.block),
+        ("jump".to_string(), &character.jump),
+        ("forward".to_string(), &character.forward),
+        ("backward".to_string(), &character.backward),
+    ];
+    if let Some(parry) = &character.parry {
+        animations.push(("parry".to_string(), parry));
+    }
+
+    for (strength, variants) in [("light", &character.moveset.light), ("heavy", &character.moveset.heavy)] {
+        for direction in AttackDirection::ALL {
+            if let Some(attack) = variants.get(direction) {
+                let field = format!("moveset.{strength}.{}.animation", direction_name(direction));
+                animations.push((field, &attack.animation));
+            }
+        }
+    }
+    animations
+}
+
+fn direction_name(direction: AttackDirection) -> &'static str {
+    match direction {
+        AttackDirection::Normal => "normal",
+        AttackDirection::Forward => "forward",
+        AttackDirection::Up => "up",
+        AttackDirection::Down => "down",
+    }
+}
+
+fn validate_animation(field: &str, animation: &Animation, errors: &mut Vec<String>) {
+    if animation.indexes.is_empty() {
+        errors.push(format!("{field}.indexes: is empty"));
+    }
+    if animation.fps == 0 {
+        errors.push(format!("{field}.fps: must be greater than 0"));
+    }
+
+    let sprites = SPRITE_COLUMNS * SPRITE_ROWS;
+    for (i, index) in animation.indexes.iter().enumerate() {
+        if *index as usize >= sprites {
+            errors.push(format!(
+                "{field}.indexes[{i}]: sprite {index} is outside the {SPRITE_COLUMNS}x{SPRITE_ROWS} sprite sheet"
+            ));
+        }
+    }
+
+    if let Some(options) = &animation.options {
+        let frame_data = options.startup_frames as u32 + options.active_frames as u32 + options.recovery_frames as u32;
+        let duration = animation.indexes.len() as u32 * animation.fps.max(1) as u32;
+        if frame_data > duration {
+            errors.push(format!(
+                "{field}.options: startup + active + recovery is {frame_data} frames, but the animation lasts {duration}"
+            ));
+        }
+    }
+
+    if animation.boxes.len() > animation.indexes.len() {
+        errors.push(format!(
+            "{field}.boxes: has {} entries for {} sprites",
+            animation.boxes.len(),
+            animation.indexes.len()
+        ));
+    }
+}
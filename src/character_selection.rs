@@ -1,7 +1,7 @@
 use bevy::{prelude::*, asset::LoadedFolder};
 use bevy::input::gamepad::{GamepadConnection, GamepadEvent};
 
-use crate::{GameState, spawn_camera, components::{Player, Character, InputController}, CharacterFolder};
+use crate::{GameState, spawn_camera, components::{Player, Character, InputController, KeyboardLayout}, controls::{Action, Controls}, CharacterFolder};
 
 const MAX_COLUMNS: u8 = 4;
 
@@ -9,15 +9,17 @@ pub struct CharacterSelectionPlugin;
 
 impl Plugin for CharacterSelectionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::CharacterSelection), (
+        app.init_resource::<KeyboardPlayers>()
+        .add_systems(OnEnter(GameState::CharacterSelection), (
                 spawn_camera, 
                 character_selection_ui,
             )
         )
         .add_systems(Update, ( 
                 check_onhover_character,
-                gamepad_input,
-                gamepad_connections
+                selection_input,
+                gamepad_connections,
+                keyboard_connections,
             ).run_if(in_state(GameState::CharacterSelection))
         )
         .add_systems(OnExit(GameState::CharacterSelection), (
@@ -28,12 +30,12 @@ impl Plugin for CharacterSelectionPlugin {
     }
 }
 
-#[derive(Component)]
-struct Selected(pub Gamepad);
-
+//layouts of the keyboard players that joined, they stay for the next matches like the gamepads
+#[derive(Resource, Default)]
+pub struct KeyboardPlayers(Vec<KeyboardLayout>);
 
 #[derive(Component, Clone, Debug)]
-pub struct SelectedCharacter(pub Character);
+pub struct SelectedCharacter(pub Character, pub AssetId<Character>);
 
 
 #[derive(Component, PartialEq, Eq, Debug)]
@@ -95,14 +97,14 @@ fn character_selection_ui(
                 let x = x as u8;
                 let col = x % MAX_COLUMNS;
                 let row = x / 4;
-                let character = pgs.get(x as usize).unwrap().clone();
+                let (character_id, character) = pgs.get(x as usize).unwrap().clone();
                 let character_name = &character.name;
                 let texture = asset_server.load(&character.sprite_face);
                 //add character name as text
                 parent.spawn((
                         Name::new(character_name.clone()),
                         CharacterSquare::new(x),
-                        SelectedCharacter(character.clone()),
+                        SelectedCharacter(character.clone(), character_id),
                         NodeBundle {
                             background_color: BackgroundColor(Color::RED),
                             style: Style {
@@ -158,11 +160,14 @@ fn despawn_all_with<C: Component>(
 
 }
 
-fn gamepad_input(
+fn selection_input(
     mut commands: Commands,
+    controls: Res<Controls>,
     axes: Res<Axis<GamepadAxis>>,
     mut next_state: ResMut<NextState<GameState>>,
     buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut keyboard_players: ResMut<KeyboardPlayers>,
 
     mut players: Query<(Entity, &InputController, &mut CharacterSquare, Option<&SelectedCharacter>), With<Player>>,
     character_squares: Query<(Entity, &CharacterSquare, &SelectedCharacter), Without<Player>>,
@@ -180,43 +185,59 @@ fn gamepad_input(
 
  
     for (entity, input_controller, mut character_square, selected) in &mut players {
-        let gamepad = input_controller.0;
-        let axis_lx = GamepadAxis { gamepad, axis_type: GamepadAxisType::LeftStickX };
-        let axis_ly = GamepadAxis { gamepad, axis_type: GamepadAxisType::LeftStickY };
-        let select_button = GamepadButton { gamepad, button_type: GamepadButtonType::East };
-        let cancel_button = GamepadButton { gamepad, button_type: GamepadButtonType::South };
-        let start_button = GamepadButton { gamepad, button_type: GamepadButtonType::Start };
+        let pressed = |action| controls.just_pressed(*input_controller, &keys, &buttons, action);
 
         //if the player did not select someone
         if selected.is_none() {
-            if let (Some(x), Some(y)) = (axes.get(axis_lx), axes.get(axis_ly)) {
-                let left_stick = Vec2::new(x, y).ceil();
-                if left_stick.x == 1. {
-                    character_square.next(player_n as u8);
-                } else if left_stick.x == -1. {
-                    character_square.prev(player_n as u8);
-                }
-
-                if left_stick.y == 1. {
-                    character_square.down(player_n as u8, MAX_COLUMNS);
-                } else if left_stick.y == -1. {
-                    character_square.up(player_n as u8, MAX_COLUMNS);
+            let last = character_numbers.saturating_sub(1);
+            if let InputController::Gamepad(gamepad) = input_controller {
+                let axis_lx = GamepadAxis { gamepad: *gamepad, axis_type: GamepadAxisType::LeftStickX };
+                let axis_ly = GamepadAxis { gamepad: *gamepad, axis_type: GamepadAxisType::LeftStickY };
+                if let (Some(x), Some(y)) = (axes.get(axis_lx), axes.get(axis_ly)) {
+                    let left_stick = Vec2::new(x, y).ceil();
+                    if left_stick.x == 1. {
+                        character_square.next(last);
+                    } else if left_stick.x == -1. {
+                        character_square.prev(last);
+                    }
+
+                    if left_stick.y == 1. {
+                        character_square.down(last, MAX_COLUMNS);
+                    } else if left_stick.y == -1. {
+                        character_square.up(last, MAX_COLUMNS);
+                    }
                 }
+            }
 
+            if pressed(Action::Forward) {
+                character_square.next(last);
+            } else if pressed(Action::Back) {
+                character_square.prev(last);
+            }
+            if pressed(Action::Down) {
+                character_square.down(last, MAX_COLUMNS);
+            } else if pressed(Action::Up) {
+                character_square.up(last, MAX_COLUMNS);
             }
 
-            if buttons.just_pressed(select_button) {
+            if pressed(Action::Confirm) {
                 if let Some((_, _, sel_character)) = character_squares.iter().find(|(_, char_pos, _)| char_pos.value() == character_square.value()) {
                     commands.entity(entity).insert(sel_character.clone());
                 }
+            } else if pressed(Action::Cancel) {
+                //keyboards are not plugged in, leaving is the only way to stop playing
+                if let InputController::Keyboard(layout) = input_controller {
+                    keyboard_players.0.retain(|joined| joined != layout);
+                    commands.entity(entity).despawn();
+                }
             }
         //if the player has already selected
         } else {
-            if buttons.just_pressed(cancel_button) {
-                commands.entity(entity).remove::<Selected>();
+            if pressed(Action::Cancel) {
+                commands.entity(entity).remove::<SelectedCharacter>();
             }
 
-            if buttons.just_pressed(start_button) && selected_n == player_n {
+            if (pressed(Action::Start) || pressed(Action::Confirm)) && selected_n == player_n {
                 next_state.set(GameState::InGame)
             }
         }
@@ -245,7 +266,7 @@ pub fn gamepad_connections(
 
                 commands.spawn((
                         Player, 
-                        InputController(ev_conn.gamepad),
+                        InputController::Gamepad(ev_conn.gamepad),
                         CharacterSquare::new(0),
                         )
                     );
@@ -253,7 +274,7 @@ pub fn gamepad_connections(
             }
             GamepadConnection::Disconnected => {
                 debug!("Lost connection with gamepad: {:?}", ev_conn.gamepad);
-                if let Some((e, _)) = query.iter().find(|(_, x)| x.0 == ev_conn.gamepad) {
+                if let Some((e, _)) = query.iter().find(|(_, x)| **x == InputController::Gamepad(ev_conn.gamepad)) {
                     commands.entity(e).despawn()
                 }
             }
@@ -261,127 +282,35 @@ pub fn gamepad_connections(
     }
 
     gamepads.iter()
-        .filter(|gamepad| !query.iter().map(|(_, input_controller)| input_controller.0).collect::<Vec<Gamepad>>().contains(gamepad))
+        .filter(|gamepad| !query.iter().any(|(_, input_controller)| *input_controller == InputController::Gamepad(*gamepad)))
         .for_each(|x| {
-            commands.spawn(( Player, InputController(x), CharacterSquare::new(0)));
+            commands.spawn(( Player, InputController::Gamepad(x), CharacterSquare::new(0)));
         })
 
 }
 
 
 
-#[derive(Component,Debug, PartialEq, Eq)]
-struct Position {
-    x: u8,
-    y: u8
-}
-
-
-
-fn keyboard_input(
-    mut player: Query<&mut Position, With<Player>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut next_state: ResMut<NextState<GameState>>,
-
-    characters: Res<CharacterFolder>,
-    loaded_folders: Res<Assets<LoadedFolder>>,
-    loader: Res<Assets<Character>>,
-
-) {
-    let loaded_folder = loaded_folders.get(&characters.0).unwrap();
-    let pgs = crate::create_characters(&loaded_folder, loader);
-
-    let character_numbers = pgs.len() as u8;
-    for mut pos in &mut player {
-        if keyboard_input.just_pressed(KeyCode::KeyW) { 
-            pos.y = (pos.y as i8 - 1).max(0) as u8 % (character_numbers / MAX_COLUMNS);
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyD) { 
-            pos.x = (pos.x as i8 + 1).max(0) as u8 % MAX_COLUMNS;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyA) { 
-            pos.x  = (pos.x as i8 - 1).max(0) as u8 %  MAX_COLUMNS;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyS) { 
-            pos.y = (pos.y as i8 + 1).max(0) as u8 % (character_numbers / MAX_COLUMNS);
-        }
-        if keyboard_input.just_pressed(KeyCode::Enter) { 
-            next_state.set(GameState::InGame)
-        }
-    }
-}
-
-
-
-fn _gamepad_input(
+//Confirm on a free layout of the keyboard joins the selection,
+//either one player on the whole keyboard or two on its halves
+fn keyboard_connections(
     mut commands: Commands,
-    axes: Res<Axis<GamepadAxis>>,
-    mut next_state: ResMut<NextState<GameState>>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-
-    mut query: Query<(&mut Position, &InputController), Without<CharacterSquare>>,
-    character_squares: Query<(Entity, &Position), (With<CharacterSquare>, Without<Selected>)>,
-    selected_characters: Query<(Entity, &Position, &Selected), With<CharacterSquare>>,
-
-    characters: Res<CharacterFolder>,
-    loaded_folders: Res<Assets<LoadedFolder>>,
-    loader: Res<Assets<Character>>,
+    controls: Res<Controls>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut keyboard_players: ResMut<KeyboardPlayers>,
+    query: Query<&InputController, With<Player>>,
 ) {
-    let loaded_folder = loaded_folders.get(&characters.0).unwrap();
-    let pgs = crate::create_characters(&loaded_folder, loader);
-
-    let character_numbers = pgs.len() as u8;
-    let selected_n = selected_characters.iter().count();
-    let player_n = query.iter().count();
-
- 
-    for (mut pos, input_controller) in &mut query {
-        let gamepad = input_controller.0;
-        let axis_lx = GamepadAxis {
-            gamepad, axis_type: GamepadAxisType::LeftStickX
-        };
-        let axis_ly = GamepadAxis {
-            gamepad, axis_type: GamepadAxisType::LeftStickY
-        };
-        if let (Some(x), Some(y)) = (axes.get(axis_lx), axes.get(axis_ly)) {
-            let left_stick = Vec2::new(x, y).ceil();
-            //da rivedere
-            if left_stick != Vec2::ZERO {
-                pos.y = (pos.y as f32 + left_stick.y).max(0.) as u8 % (character_numbers / MAX_COLUMNS).clamp(1, 100);
-                pos.x = (pos.x as f32 + left_stick.x).max(0.) as u8 % MAX_COLUMNS;
-            }
-        }
-
-        let select_button = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::East
-        };
-
-        let cancel_button = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::South
-        };
-
-        let start_button = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::Start
-        };
-
-        if buttons.just_pressed(select_button) {
-            if let Some((e, _)) = character_squares.iter().find(|(_, char_pos)| **char_pos == *pos) {
-                commands.entity(e).insert(Selected(gamepad));
-            }
-        }
-
-        if buttons.just_pressed(cancel_button) {
-            if let Some((e, _)) = character_squares.iter().find(|(_, char_pos)| **char_pos == *pos) {
-                commands.entity(e).remove::<Selected>();
-            }
+    for layout in KeyboardLayout::ALL {
+        let free = !keyboard_players.0.iter().any(|joined| joined.overlaps(layout));
+        if free && controls.key_just_pressed(&keys, layout, Action::Confirm) {
+            keyboard_players.0.push(layout);
         }
+    }
 
-        if buttons.just_pressed(start_button) && selected_n == player_n {
-            next_state.set(GameState::InGame)
+    //players come back after a match, like the connected gamepads
+    for layout in keyboard_players.0.iter() {
+        if !query.iter().any(|input_controller| *input_controller == InputController::Keyboard(*layout)) {
+            commands.spawn(( Player, InputController::Keyboard(*layout), CharacterSquare::new(0)));
         }
-
     }
 }
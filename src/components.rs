@@ -1,10 +1,13 @@
 use std::collections::HashMap;
-use std::time::Duration;
 
 use bevy::prelude::*;
 use bevy::reflect::TypePath;
 
 pub const MAX_FRAME_RATE: u8 = 60;
+//every sprite sheet is a grid of square sprites
+pub const SPRITE_SIZE: f32 = 64.;
+pub const SPRITE_COLUMNS: usize = 3;
+pub const SPRITE_ROWS: usize = 3;
 
 #[derive(serde::Deserialize, Asset, TypePath, Debug, Clone)]
 pub struct Character {
@@ -14,19 +17,81 @@ pub struct Character {
     pub speed: f32,
     pub health: u32,
     pub hurtbox: Hurtbox,
+    pub pushbox: Pushbox,
     pub moveset: Moveset,
     pub idle: Animation,
     pub block: Animation,
     pub jump: Animation,
     pub forward: Animation,
     pub backward: Animation,
+    //its active frames are the parry window
+    #[serde(default)]
+    pub parry: Option<Animation>,
+}
+
+
+impl Character {
+    pub fn animations(&self) -> HashMap<AnimationState, (AnimationManager, Option<Attack>)> {
+        let mut animations = HashMap::from([
+            (AnimationState::Idle, (AnimationManager::new(self.idle.clone()), None)),
+            (AnimationState::Jump, (AnimationManager::new(self.jump.clone()), None)),
+            (AnimationState::Block, (AnimationManager::new(self.block.clone()), None)),
+            (AnimationState::Forward, (AnimationManager::new(self.forward.clone()), None)),
+            (AnimationState::Backward, (AnimationManager::new(self.backward.clone()), None)),
+        ]);
+
+        if let Some(parry) = &self.parry {
+            animations.insert(AnimationState::Parry, (AnimationManager::new(parry.clone()), None));
+        }
+
+        for direction in AttackDirection::ALL {
+            if let Some(light) = self.moveset.light.get(direction) {
+                animations.insert(
+                    AnimationState::LightAttack(direction),
+                    (AnimationManager::new(light.animation.clone()), Some(light.clone()))
+                );
+            }
+            if let Some(heavy) = self.moveset.heavy.get(direction) {
+                animations.insert(
+                    AnimationState::HeavyAttack(direction),
+                    (AnimationManager::new(heavy.animation.clone()), Some(heavy.clone()))
+                );
+            }
+        }
+
+        animations
+    }
 }
 
 
 #[derive(serde::Deserialize, Asset, TypePath, Component, Debug, Clone)]
 pub struct Moveset {
-    pub light: Attack,
-    pub heavy: Attack 
+    pub light: AttackVariants,
+    pub heavy: AttackVariants 
+}
+
+//every variant is optional, a character can miss any of them
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct AttackVariants {
+    #[serde(default)]
+    pub normal: Option<Attack>,
+    #[serde(default)]
+    pub forward: Option<Attack>,
+    #[serde(default)]
+    pub up: Option<Attack>,
+    #[serde(default)]
+    pub down: Option<Attack>,
+}
+
+impl AttackVariants {
+    pub fn get(&self, direction: AttackDirection) -> Option<&Attack> {
+        match direction {
+            AttackDirection::Normal => self.normal.as_ref(),
+            AttackDirection::Forward => self.forward.as_ref(),
+            AttackDirection::Up => self.up.as_ref(),
+            AttackDirection::Down => self.down.as_ref(),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Asset, TypePath, Debug, Clone)]
@@ -35,6 +100,61 @@ pub struct Attack {
     pub hit_stun_frames: u8,
     pub animation: Animation,
     pub hitbox: Hitbox,
+    //damage and stun applied when the attack is blocked
+    #[serde(default)]
+    pub chip_damage: u32,
+    #[serde(default)]
+    pub block_stun_frames: u8,
+    #[serde(default)]
+    pub cancel: CancelOptions,
+    //game frames both fighters freeze when the attack connects
+    #[serde(default)]
+    pub hitstop_frames: u8,
+    #[serde(default)]
+    pub knockback: Knockback,
+    //times the attack can connect with the same target in one activation, once if missing
+    #[serde(default)]
+    pub hits: Option<u8>,
+}
+
+impl Attack {
+    pub fn max_hits(&self) -> u8 {
+        self.hits.unwrap_or(1).max(1)
+    }
+
+    //game frames between two hits of a multi-hit attack on the same target
+    pub fn rehit_frames(&self) -> u8 {
+        let active = self.animation.options.as_ref().map_or(1, |options| options.active_frames);
+        (active / self.max_hits()).max(1)
+    }
+}
+
+//velocities given to the victim, x points away from the attacker
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct Knockback {
+    //horizontal speed pushing back a grounded or blocking victim
+    #[serde(default)]
+    pub pushback: f32,
+    //sends the victim airborne, even when hit on the ground
+    #[serde(default)]
+    pub launch: Option<Launch>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct Launch {
+    pub x: f32,
+    pub y: f32,
+}
+
+//how an attack can be interrupted during its startup frames
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct CancelOptions {
+    //the fake attack input stops the attack
+    #[serde(default)]
+    pub fake: bool,
+    //another attack replaces it immediately
+    #[serde(default)]
+    pub into_attack: bool,
 }
 
 //Animations ----
@@ -47,11 +167,37 @@ pub enum AnimationState {
     Backward,
     Jump,
     Crouch,
-    LightAttack,
-    HeavyAttack,
+    Parry,
+    LightAttack(AttackDirection),
+    HeavyAttack(AttackDirection),
+}
+
+impl AnimationState {
+    pub fn is_attack(&self) -> bool {
+        matches!(self, AnimationState::LightAttack(_) | AnimationState::HeavyAttack(_))
+    }
+}
+
+//direction held while pressing an attack button
+#[derive(Default, serde::Deserialize, Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum AttackDirection {
+    #[default]
+    Normal,
+    Forward,
+    Up,
+    Down,
 }
 
-#[derive(Component, Default)]
+impl AttackDirection {
+    pub const ALL: [AttackDirection; 4] = [
+        AttackDirection::Normal,
+        AttackDirection::Forward,
+        AttackDirection::Up,
+        AttackDirection::Down,
+    ];
+}
+
+#[derive(Component, Default, Clone)]
 pub struct PlayerAnimationManagement {
     pub state: AnimationState,
     pub next_state: Option<AnimationState>,
@@ -63,15 +209,32 @@ impl PlayerAnimationManagement {
         Self { animations, ..default() }
     }
 
+    pub fn has_animation(&self, state: &AnimationState) -> bool {
+        self.animations.contains_key(state)
+    }
+
     pub fn request_animation(&mut self, state: AnimationState) -> bool {
+        //the character does not have this move
+        if !self.has_animation(&state) {
+            return false;
+        }
+
         match self.state {
             //if the current animation is an attack and is running...
-            AnimationState::LightAttack | AnimationState::HeavyAttack if self.next_state.is_none() => {
+            ref current if current.is_attack() && self.next_state.is_none() => {
                 match state {
                     //...and i want to link an attack after it
-                    AnimationState::LightAttack | AnimationState::HeavyAttack => {
+                    ref next if next.is_attack() => {
+                        let (animation_manager, attack) = self.get_current_animation();
+                        //..and it is still starting up and allows to be cancelled
+                        let can_cancel = attack.as_ref().is_some_and(|attack| attack.cancel.into_attack);
+                        if can_cancel && animation_manager.is_within_startup() && *next != self.state {
+                            //..it gets replaced by the new attack
+                            self.state = state;
+                            self.get_current_animation_mut().0.reset();
+                            return true
+                        }
                         //..and it falls within the recovery frames
-                        let (animation_manager, _) = self.get_current_animation();
                         if animation_manager.is_within_recovery() {
                             //..it gets buffered
                             self.next_state = Some(state);
@@ -85,14 +248,16 @@ impl PlayerAnimationManagement {
                 }
             },
 
-            AnimationState::LightAttack | AnimationState::HeavyAttack if self.next_state.is_some() => { },
+            ref current if current.is_attack() && self.next_state.is_some() => { },
+            //a parry cannot be cancelled, it is a commitment
+            AnimationState::Parry => { },
             //if current state is not critical
             //therefore is not an attack
             //the prev animation get canceled and the new one become imediatly active
             _ => {
                 if state != self.state {
                     self.state = state;
-                    self.get_current_animation_mut().0.start_timer(MAX_FRAME_RATE);
+                    self.get_current_animation_mut().0.reset();
                 }
                 return true;
             }
@@ -101,6 +266,51 @@ impl PlayerAnimationManagement {
         return false;
    }
 
+    //fake attack: stops the current attack if it is still starting up
+    pub fn cancel_startup(&mut self) -> bool {
+        if !self.state.is_attack() {
+            return false;
+        }
+
+        let (animation_manager, attack) = self.get_current_animation();
+        let can_cancel = attack.as_ref().is_some_and(|attack| attack.cancel.fake);
+        if !can_cancel || !animation_manager.is_within_startup() {
+            return false;
+        }
+
+        self.interrupt();
+        true
+    }
+
+    pub fn is_parrying(&self) -> bool {
+        self.state == AnimationState::Parry && self.get_current_animation().0.is_within_active()
+    }
+
+    //drops the current and buffered animation and goes back to default
+    pub fn interrupt(&mut self) {
+        self.next_state = None;
+        self.state = AnimationState::default();
+        self.get_current_animation_mut().0.reset();
+    }
+
+    //swaps in new animation data keeping the progress of every animation,
+    //a state the character does not have anymore goes back to default
+    pub fn reload(&mut self, mut animations: HashMap<AnimationState, (AnimationManager, Option<Attack>)>) {
+        for (state, (animation_manager, _)) in animations.iter_mut() {
+            if let Some((current, _)) = self.animations.get(state) {
+                animation_manager.frame = current.frame;
+            }
+        }
+        self.animations = animations;
+
+        if self.next_state.as_ref().is_some_and(|next| !self.has_animation(next)) {
+            self.next_state = None;
+        }
+        if !self.has_animation(&self.state) {
+            self.interrupt();
+        }
+    }
+
     //start next animation buffered if any
     //else it just uses default (in this case is idle)
     pub fn shift(&mut self) {
@@ -111,8 +321,7 @@ impl PlayerAnimationManagement {
             self.state = AnimationState::default();
         }
         let (animation, _) = &mut self.get_current_animation_mut();
-        animation.reset(MAX_FRAME_RATE);
-        animation.start_timer(MAX_FRAME_RATE);
+        animation.reset();
 
     }
 
@@ -138,95 +347,82 @@ impl PlayerAnimationManagement {
 
 #[derive(Debug, Clone)]
 pub struct AnimationManager {
-    index: usize,
     animation: Animation,
-    timer: Timer
+    //game frames elapsed since the animation started
+    frame: u16,
 }
 
 impl AnimationManager {
     pub fn new(animation: Animation) -> Self {
         Self {
             animation,
-            index: 0,
-            timer: Timer::from_seconds(0., TimerMode::Once)
+            frame: 0,
         }
     }
 
-    pub fn reset(&mut self, max_fps: u8) {
-        self.index = 0;
-        self.timer = Timer::from_seconds(
-            self.animation.fps as f32 / max_fps as f32, 
-            TimerMode::Once
-        )
+    pub fn reset(&mut self) {
+        self.frame = 0;
     }
 
-
-
-    pub fn start_timer(&mut self, max_fps: u8) {
-        self.timer = Timer::from_seconds(
-            self.animation.fps as f32 / max_fps as f32, 
-            TimerMode::Once
-        )
-    }
-
-
-    pub fn tick(&mut self, duration: Duration) {
-        self.timer.tick(duration);
+    //advance the animation by one game frame
+    pub fn tick(&mut self) {
+        self.frame = self.frame.saturating_add(1);
     }
 
 
     pub fn is_within_active(&self) -> bool {
         if let Some(options) = &self.animation.options {
+            let startup = options.startup_frames as u16;
             return 
-                self.index > options.startup_frames.into()
+                self.frame >= startup
                 &&
-                self.index <= (options.active_frames + options.startup_frames).into()
+                self.frame < startup + options.active_frames as u16
         }
         return false
     }
 
     pub fn is_within_startup(&self) -> bool {
         if let Some(options) = &self.animation.options {
-            return self.index < options.startup_frames.into()
+            return self.frame < options.startup_frames.into()
         }
         return false   
     }
 
     pub fn is_within_recovery(&self) -> bool {
         if let Some(options) = &self.animation.options {
+            let active_end = options.startup_frames as u16 + options.active_frames as u16;
             return 
-                self.index > (options.active_frames + options.startup_frames).into()
+                self.frame >= active_end
                 &&
-                self.index <= (options.active_frames + options.startup_frames + options.recovery_frames).into()
+                self.frame < active_end + options.recovery_frames as u16
         }
         return false  
     }
 
-
-    pub fn is_just_finished(&self) -> bool {
-        self.timer.just_finished()
+    //total length of the animation in game frames
+    pub fn duration(&self) -> u16 {
+        self.animation.indexes.len() as u16 * self.frames_per_sprite()
     }
 
     pub fn is_finished(&self) -> bool {
-        self.timer.finished()
+        self.frame >= self.duration()
+    }
+
+    pub fn remaining_frames(&self) -> u16 {
+        self.duration().saturating_sub(self.frame)
     }
 
-    pub fn is_last_frame(&self) -> bool {
-        self.animation.indexes.len() - 1 == self.index
+    pub fn options(&self) -> Option<&AnimationOptions> {
+        self.animation.options.as_ref()
     }
 
-    pub fn next_frame(&mut self, max_fps: u8) {
-        if self.animation.indexes.len() > self.index + 1 {
-            self.index += 1;
-            self.timer = Timer::from_seconds(
-                self.animation.fps as f32 / max_fps as f32, 
-                TimerMode::Once
-            )
-        } 
+    pub fn current_boxes(&self) -> Option<&FrameBoxes> {
+        self.animation.boxes.get(self.current_frame_index() as usize)
     }
 
     pub fn current_frame_index(&self) -> u8 {
-        self.index as u8
+        let index = self.frame / self.frames_per_sprite();
+        index.min(self.animation.indexes.len().saturating_sub(1) as u16) as u8
     }
 
     
@@ -235,9 +431,12 @@ impl AnimationManager {
     }
 
     pub fn current_sprite_index(&self) -> Option<u8> {
-        self.animation.indexes.get(self.index).cloned()
+        self.animation.indexes.get(self.current_frame_index() as usize).cloned()
     }
 
+    fn frames_per_sprite(&self) -> u16 {
+        (self.animation.fps as u16).max(1)
+    }
 
 }
 
@@ -245,11 +444,26 @@ impl AnimationManager {
 #[derive(serde::Deserialize, Asset, TypePath, Debug, Clone)]
 pub struct Animation {
     pub indexes: Vec<u8>,
+    //number of game frames each sprite stays on screen
     pub fps: u8,
-    pub options: Option<AnimationOptions>
+    pub options: Option<AnimationOptions>,
+    //boxes of each sprite in `indexes`, in the same order
+    #[serde(default)]
+    pub boxes: Vec<FrameBoxes>,
+}
+
+//boxes replacing the character hurtbox and the attack hitbox for one sprite,
+//an empty list means none at all (like an invincible or whiffing frame)
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct FrameBoxes {
+    #[serde(default)]
+    pub hitboxes: Option<Vec<Hitbox>>,
+    #[serde(default)]
+    pub hurtboxes: Option<Vec<Hurtbox>>,
 }
 
 #[derive(serde::Deserialize, Asset, TypePath, Debug, Clone)]
+//frame data, counted in game frames from the start of the animation
 pub struct AnimationOptions {
     pub startup_frames: u8,
     pub active_frames: u8,
@@ -274,67 +488,411 @@ pub struct Hurtbox {
     pub height: f32
 }
 
+//boxes of the current frame
+#[derive(Component, Debug, Clone, Default)]
+pub struct Hitboxes(pub Vec<Hitbox>);
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct Hurtboxes(pub Vec<Hurtbox>);
+
+//body of the fighter, two pushboxes can never overlap
+#[derive(serde::Deserialize, Asset, TypePath, Debug, Clone, Component)]
+pub struct Pushbox {
+    pub x: f32,
+    pub y: f32,
+    pub length: f32,
+    pub height: f32
+}
+
+//offsets are authored for a fighter facing right
+impl Hitbox {
+    pub fn offset(&self, facing: Facing) -> Vec2 {
+        Vec2::new(self.x * facing.sign(), self.y)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.length, self.height)
+    }
+}
+
+impl Hurtbox {
+    pub fn offset(&self, facing: Facing) -> Vec2 {
+        Vec2::new(self.x * facing.sign(), self.y)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.length, self.height)
+    }
+}
+
+impl Pushbox {
+    pub fn offset(&self, facing: Facing) -> Vec2 {
+        Vec2::new(self.x * facing.sign(), self.y)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.length, self.height)
+    }
+}
+
 #[derive(Event, Debug)]
 pub struct HitEvent {
     pub target: Entity,
     pub source: Entity,
 }
 
+//an attack landed inside the parry window of the defender
+#[derive(Event, Debug)]
+pub struct ParryEvent {
+    pub defender: Entity,
+    pub attacker: Entity,
+}
+
+//remaining game frames of hitstun
 #[derive(Component, Clone, Debug)]
-pub struct HitStun(Timer);
+pub struct HitStun(u8);
 impl HitStun {
-   pub fn new(fps: u8, max_fps: u8) -> Self {
-        Self(Timer::from_seconds( fps as f32 / max_fps as f32, TimerMode::Once))
+   pub fn new(frames: u8) -> Self {
+        Self(frames)
+   } 
+
+   pub fn tick(&mut self) {
+       self.0 = self.0.saturating_sub(1);
+   }
+
+   pub fn is_finished(&self) -> bool {
+       self.0 == 0
+   }
+}
+
+//remaining game frames frozen on contact, animations and movement stop
+#[derive(Component, Clone, Debug)]
+pub struct HitStop(u8);
+impl HitStop {
+   pub fn new(frames: u8) -> Self {
+        Self(frames)
+   } 
+
+   pub fn tick(&mut self) {
+       self.0 = self.0.saturating_sub(1);
+   }
+
+   pub fn is_finished(&self) -> bool {
+       self.0 == 0
+   }
+}
+
+//targets already hit by the current activation of an attack
+#[derive(Component, Clone, Debug, Default)]
+pub struct AttackHits {
+    targets: Vec<AttackTarget>,
+}
+
+#[derive(Clone, Debug)]
+struct AttackTarget {
+    entity: Entity,
+    hits: u8,
+    //game frames before it can be hit again
+    cooldown: u8,
+}
+
+impl AttackHits {
+    pub fn tick(&mut self) {
+        for target in self.targets.iter_mut() {
+            target.cooldown = target.cooldown.saturating_sub(1);
+        }
+    }
+
+    pub fn can_hit(&self, entity: Entity, max_hits: u8) -> bool {
+        match self.targets.iter().find(|target| target.entity == entity) {
+            Some(target) => target.hits < max_hits && target.cooldown == 0,
+            None => true,
+        }
+    }
+
+    pub fn register(&mut self, entity: Entity, cooldown: u8) {
+        match self.targets.iter_mut().find(|target| target.entity == entity) {
+            Some(target) => {
+                target.hits += 1;
+                target.cooldown = cooldown;
+            },
+            None => self.targets.push(AttackTarget { entity, hits: 1, cooldown }),
+        }
+    }
+}
+
+//airborne after being hit, the fighter cannot act until it lands
+#[derive(Component, Clone, Debug)]
+pub struct Juggle {
+    //hits taken since leaving the ground
+    pub hits: u8,
+}
+
+impl Juggle {
+    pub fn add(&mut self) {
+        self.hits = self.hits.saturating_add(1);
+    }
+}
+
+//remaining game frames stuck in the block animation after blocking a hit
+#[derive(Component, Clone, Debug)]
+pub struct BlockStun(u8);
+impl BlockStun {
+   pub fn new(frames: u8) -> Self {
+        Self(frames)
    } 
 
+   pub fn tick(&mut self) {
+       self.0 = self.0.saturating_sub(1);
+   }
+
    pub fn is_finished(&self) -> bool {
-       self.0.finished()
+       self.0 == 0
    }
 }
 
+//hits taken in a row without leaving hitstun
 #[derive(Component, Clone, Debug)]
-pub struct Health(u32);
+pub struct Combo {
+    pub hits: u16,
+    pub damage: u32,
+}
+
+impl Combo {
+    pub fn new(damage: u32) -> Self {
+        Self { hits: 1, damage }
+    }
+
+    pub fn add(&mut self, damage: u32) {
+        self.hits = self.hits.saturating_add(1);
+        self.damage += damage;
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Health {
+    value: u32,
+    max: u32,
+}
 
 impl Health {
     pub fn new(value: u32) -> Self {
-        Self(value)
+        Self { value, max: value }
     }
 
     pub fn heal(&mut self, value: u32, max_health: u32) {
-        if self.0 + value > max_health {
-            self.0 = max_health;
+        if self.value + value > max_health {
+            self.value = max_health;
         } else {
-            self.0 += value;
+            self.value += value;
         }
     }
 
     pub fn damage(&mut self, value: u32) {
-        if self.0 - value < 0 {
-            self.0 = 0;
-        } else {
-            self.0 -= value;
-        }
+        self.value = self.value.saturating_sub(value);
+    }
+
+    pub fn refill(&mut self) {
+        self.value = self.max;
     }
 
     pub fn value(&self) -> u32 {
-        self.0
+        self.value
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    pub fn is_ko(&self) -> bool {
+        self.value == 0
     }
 }
 
 #[derive(Component)]
 pub struct Player;
 
+//character file the fighter was built from
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CharacterAsset(pub AssetId<Character>);
+
 #[derive(Component)]
 pub struct Speed(pub f32);
 
 #[derive(Component)]
 pub struct IsBlocking;
 
-#[derive(Component)]
-pub struct InputController(pub Gamepad);
+//the device a player uses, from the character selection to the end of the match
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputController {
+    Gamepad(Gamepad),
+    Keyboard(KeyboardLayout),
+}
+
+//two players can share one keyboard, each on one half of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyboardLayout {
+    Full,
+    Left,
+    Right,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 3] = [KeyboardLayout::Full, KeyboardLayout::Left, KeyboardLayout::Right];
+
+    //the full layout uses keys of both halves
+    pub fn overlaps(&self, other: KeyboardLayout) -> bool {
+        *self == other || *self == KeyboardLayout::Full || other == KeyboardLayout::Full
+    }
+}
+
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facing {
+    #[default]
+    Right,
+    Left,
+}
+
+impl Facing {
+    pub fn sign(&self) -> f32 {
+        match self {
+            Facing::Right => 1.,
+            Facing::Left => -1.,
+        }
+    }
+}
 
-#[derive(Component, Default)]
+//which player's inputs drive this fighter
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlayerHandle(pub usize);
+
+#[derive(Component, Default, Clone)]
 pub struct Velocity {
     pub x: f32,
     pub y: f32,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputButton {
+    Up = 1 << 0,
+    Down = 1 << 1,
+    Left = 1 << 2,
+    Right = 1 << 3,
+    Light = 1 << 4,
+    Heavy = 1 << 5,
+    Block = 1 << 6,
+    Fake = 1 << 7,
+    Parry = 1 << 8,
+}
+
+//buttons held by a player during a single game frame
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InputFrame(pub u16);
+
+impl InputFrame {
+    pub fn set(&mut self, button: InputButton, pressed: bool) {
+        if pressed {
+            self.0 |= button as u16;
+        } else {
+            self.0 &= !(button as u16);
+        }
+    }
+
+    pub fn pressed(&self, button: InputButton) -> bool {
+        self.0 & button as u16 != 0
+    }
+}
+
+//input sampled every render frame and consumed by the fixed simulation step
+#[derive(Component, Default, Clone, Copy, Debug)]
+pub struct PlayerInput {
+    pub current: InputFrame,
+    pub previous: InputFrame,
+}
+
+impl PlayerInput {
+    pub fn pressed(&self, button: InputButton) -> bool {
+        self.current.pressed(button)
+    }
+
+    pub fn just_pressed(&self, button: InputButton) -> bool {
+        self.current.pressed(button) && !self.previous.pressed(button)
+    }
+
+    //forward and back depend on which side the fighter is facing
+    pub fn forward_pressed(&self, facing: Facing) -> bool {
+        match facing {
+            Facing::Right => self.pressed(InputButton::Right),
+            Facing::Left => self.pressed(InputButton::Left),
+        }
+    }
+
+    pub fn back_pressed(&self, facing: Facing) -> bool {
+        match facing {
+            Facing::Right => self.pressed(InputButton::Left),
+            Facing::Left => self.pressed(InputButton::Right),
+        }
+    }
+
+    //up and down take priority over forward
+    pub fn attack_direction(&self, facing: Facing) -> AttackDirection {
+        if self.pressed(InputButton::Up) {
+            AttackDirection::Up
+        } else if self.pressed(InputButton::Down) {
+            AttackDirection::Down
+        } else if self.forward_pressed(facing) {
+            AttackDirection::Forward
+        } else {
+            AttackDirection::Normal
+        }
+    }
+
+    //called once the simulation consumed the current frame
+    pub fn advance(&mut self) {
+        self.previous = self.current;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_hits_each_target_once_by_default() {
+        let (first, second) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut hits = AttackHits::default();
+        assert!(hits.can_hit(first, 1));
+
+        hits.register(first, 0);
+        assert!(!hits.can_hit(first, 1));
+        assert!(hits.can_hit(second, 1));
+    }
+
+    #[test]
+    fn attack_hits_waits_for_the_rehit_cooldown() {
+        let target = Entity::from_raw(1);
+        let mut hits = AttackHits::default();
+
+        hits.register(target, 2);
+        assert!(!hits.can_hit(target, 3));
+        hits.tick();
+        assert!(!hits.can_hit(target, 3));
+        hits.tick();
+        assert!(hits.can_hit(target, 3));
+
+        //the hits count stops the attack whatever the cooldown
+        hits.register(target, 0);
+        assert!(hits.can_hit(target, 3));
+        hits.register(target, 0);
+        assert!(!hits.can_hit(target, 3));
+    }
+
+    #[test]
+    fn juggle_counts_saturate() {
+        let mut juggle = Juggle { hits: u8::MAX - 1 };
+        juggle.add();
+        juggle.add();
+        assert_eq!(juggle.hits, u8::MAX);
+    }
+}
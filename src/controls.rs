@@ -0,0 +1,512 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::components::{InputButton, InputController, InputFrame, KeyboardLayout};
+
+const CONFIG_FOLDER: &str = "supa-studio-fighter";
+const CONTROLS_FILE: &str = "controls.ron";
+const STICK_THRESHOLD: f32 = 0.5;
+
+
+//loads the bindings of every device from the user config folder
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Controls::load());
+    }
+}
+
+
+//what a player wants to do, whatever device and button they use
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    Up,
+    Down,
+    Back,
+    Forward,
+    Light,
+    Heavy,
+    Block,
+    Fake,
+    Parry,
+    Start,
+    Confirm,
+    Cancel,
+    //training mode only
+    Record,
+    Playback,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::Up,
+        Action::Down,
+        Action::Back,
+        Action::Forward,
+        Action::Light,
+        Action::Heavy,
+        Action::Block,
+        Action::Fake,
+        Action::Parry,
+        Action::Start,
+        Action::Confirm,
+        Action::Cancel,
+        Action::Record,
+        Action::Playback,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::Back => "Back",
+            Action::Forward => "Forward",
+            Action::Light => "Light",
+            Action::Heavy => "Heavy",
+            Action::Block => "Block",
+            Action::Fake => "Fake",
+            Action::Parry => "Parry",
+            Action::Start => "Start",
+            Action::Confirm => "Confirm",
+            Action::Cancel => "Cancel",
+            Action::Record => "Record",
+            Action::Playback => "Playback",
+        }
+    }
+
+    //back and forward are bound as seen from the left side of the stage,
+    //the combat turns them around with the facing of the fighter
+    pub fn button(&self) -> Option<InputButton> {
+        match self {
+            Action::Up => Some(InputButton::Up),
+            Action::Down => Some(InputButton::Down),
+            Action::Back => Some(InputButton::Left),
+            Action::Forward => Some(InputButton::Right),
+            Action::Light => Some(InputButton::Light),
+            Action::Heavy => Some(InputButton::Heavy),
+            Action::Block => Some(InputButton::Block),
+            Action::Fake => Some(InputButton::Fake),
+            Action::Parry => Some(InputButton::Parry),
+            Action::Start | Action::Confirm | Action::Cancel | Action::Record | Action::Playback => None,
+        }
+    }
+
+    //an action read in the same place as `other`, the two cannot share an input
+    pub fn conflicts_with(&self, other: Action) -> bool {
+        *self != other && CONFLICT_GROUPS.iter().any(|group| group.contains(self) && group.contains(&other))
+    }
+}
+
+//actions read at the same time: during a fight and in the menus,
+//a fight and a menu action can share an input as one is never read next to the other
+const CONFLICT_GROUPS: [&[Action]; 2] = [
+    &[
+        Action::Up, Action::Down, Action::Back, Action::Forward,
+        Action::Light, Action::Heavy, Action::Block, Action::Fake, Action::Parry,
+        Action::Start, Action::Record, Action::Playback,
+    ],
+    &[
+        Action::Up, Action::Down, Action::Back, Action::Forward,
+        Action::Start, Action::Confirm, Action::Cancel,
+    ],
+];
+
+pub type Bindings<T> = BTreeMap<Action, Vec<T>>;
+
+//an action conflicting with `action` that shares one of its inputs
+pub fn conflict<T: PartialEq>(bindings: &Bindings<T>, action: Action) -> Option<Action> {
+    let inputs = bindings.get(&action)?;
+    bindings.iter()
+        .find(|(other, other_inputs)| action.conflicts_with(**other) && other_inputs.iter().any(|input| inputs.contains(input)))
+        .map(|(other, _)| *other)
+}
+
+//binds `input` to `action` alone, a conflicting action that used it
+//gets the previous binding of `action` instead,
+//nothing changes if that would leave two actions conflicting
+pub fn rebind<T: Copy + PartialEq>(bindings: &mut Bindings<T>, action: Action, input: T) -> bool {
+    let mut rebound = bindings.clone();
+    let previous = rebound.insert(action, vec![input]).unwrap_or_default();
+    let mut changed = vec![action];
+    for (other, inputs) in rebound.iter_mut() {
+        if !action.conflicts_with(*other) || !inputs.contains(&input) {
+            continue;
+        }
+        inputs.retain(|bound| *bound != input);
+        for bound in previous.iter() {
+            if *bound != input && !inputs.contains(bound) {
+                inputs.push(*bound);
+            }
+        }
+        changed.push(*other);
+    }
+
+    if changed.iter().any(|action| conflict(&rebound, *action).is_some()) {
+        return false;
+    }
+    *bindings = rebound;
+    true
+}
+
+//the buttons bound to every action, one set per kind of device
+#[derive(Resource, serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Controls {
+    pub keyboard: Bindings<KeyCode>,
+    //the halves used when two players share the keyboard
+    pub keyboard_left: Bindings<KeyCode>,
+    pub keyboard_right: Bindings<KeyCode>,
+    pub gamepad: Bindings<GamepadButtonType>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        let keyboard = [
+            (Action::Up, KeyCode::KeyW),
+            (Action::Down, KeyCode::KeyS),
+            (Action::Back, KeyCode::KeyA),
+            (Action::Forward, KeyCode::KeyD),
+            (Action::Light, KeyCode::KeyZ),
+            (Action::Heavy, KeyCode::KeyX),
+            (Action::Block, KeyCode::KeyQ),
+            (Action::Fake, KeyCode::KeyC),
+            (Action::Parry, KeyCode::KeyE),
+            (Action::Start, KeyCode::Escape),
+            (Action::Confirm, KeyCode::Enter),
+            (Action::Cancel, KeyCode::Backspace),
+            (Action::Record, KeyCode::KeyR),
+            (Action::Playback, KeyCode::KeyT),
+        ];
+        let keyboard_left = [
+            (Action::Up, KeyCode::KeyW),
+            (Action::Down, KeyCode::KeyS),
+            (Action::Back, KeyCode::KeyA),
+            (Action::Forward, KeyCode::KeyD),
+            (Action::Light, KeyCode::KeyF),
+            (Action::Heavy, KeyCode::KeyG),
+            (Action::Block, KeyCode::KeyR),
+            (Action::Fake, KeyCode::KeyH),
+            (Action::Parry, KeyCode::KeyT),
+            (Action::Start, KeyCode::Escape),
+            (Action::Confirm, KeyCode::KeyF),
+            (Action::Cancel, KeyCode::KeyG),
+            (Action::Record, KeyCode::Digit1),
+            (Action::Playback, KeyCode::Digit2),
+        ];
+        let keyboard_right = [
+            (Action::Up, KeyCode::ArrowUp),
+            (Action::Down, KeyCode::ArrowDown),
+            (Action::Back, KeyCode::ArrowLeft),
+            (Action::Forward, KeyCode::ArrowRight),
+            (Action::Light, KeyCode::Comma),
+            (Action::Heavy, KeyCode::Period),
+            (Action::Block, KeyCode::KeyL),
+            (Action::Fake, KeyCode::Slash),
+            (Action::Parry, KeyCode::Semicolon),
+            (Action::Start, KeyCode::Enter),
+            (Action::Confirm, KeyCode::Comma),
+            (Action::Cancel, KeyCode::Period),
+            (Action::Record, KeyCode::Digit9),
+            (Action::Playback, KeyCode::Digit0),
+        ];
+        let gamepad = [
+            (Action::Up, GamepadButtonType::DPadUp),
+            (Action::Down, GamepadButtonType::DPadDown),
+            (Action::Back, GamepadButtonType::DPadLeft),
+            (Action::Forward, GamepadButtonType::DPadRight),
+            (Action::Light, GamepadButtonType::West),
+            (Action::Heavy, GamepadButtonType::North),
+            (Action::Block, GamepadButtonType::RightTrigger2),
+            (Action::Fake, GamepadButtonType::LeftTrigger2),
+            (Action::Parry, GamepadButtonType::RightTrigger),
+            (Action::Start, GamepadButtonType::Start),
+            (Action::Confirm, GamepadButtonType::East),
+            (Action::Cancel, GamepadButtonType::South),
+            (Action::Record, GamepadButtonType::Select),
+            (Action::Playback, GamepadButtonType::LeftTrigger),
+        ];
+
+        Self {
+            keyboard: keyboard.into_iter().map(|(action, key)| (action, vec![key])).collect(),
+            keyboard_left: keyboard_left.into_iter().map(|(action, key)| (action, vec![key])).collect(),
+            keyboard_right: keyboard_right.into_iter().map(|(action, key)| (action, vec![key])).collect(),
+            gamepad: gamepad.into_iter().map(|(action, button)| (action, vec![button])).collect(),
+        }
+    }
+}
+
+impl Controls {
+    pub fn path() -> Option<PathBuf> {
+        Some(config_dir()?.join(CONFIG_FOLDER).join(CONTROLS_FILE))
+    }
+
+    //a missing or broken file falls back to the default bindings
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        match Self::parse(&text) {
+            Ok(controls) => {
+                info!("Loaded controls from {}", path.display());
+                controls
+            },
+            Err(err) => {
+                warn!("Could not read controls {}: {err}", path.display());
+                Self::default()
+            },
+        }
+    }
+
+    //actions added after the file was written keep their default
+    fn parse(text: &str) -> Result<Self, ron::de::SpannedError> {
+        let mut controls: Controls = ron::de::from_str(text)?;
+        let defaults = Self::default();
+        for layout in KeyboardLayout::ALL {
+            for (action, keys) in defaults.layout(layout) {
+                controls.layout_mut(layout).entry(*action).or_insert(keys.clone());
+            }
+        }
+        for (action, buttons) in defaults.gamepad {
+            controls.gamepad.entry(action).or_insert(buttons);
+        }
+        Ok(controls)
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path().ok_or("no config folder for this user")?;
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder).map_err(|err| err.to_string())?;
+        }
+        fs::write(&path, text).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    pub fn layout(&self, layout: KeyboardLayout) -> &Bindings<KeyCode> {
+        match layout {
+            KeyboardLayout::Full => &self.keyboard,
+            KeyboardLayout::Left => &self.keyboard_left,
+            KeyboardLayout::Right => &self.keyboard_right,
+        }
+    }
+
+    pub fn layout_mut(&mut self, layout: KeyboardLayout) -> &mut Bindings<KeyCode> {
+        match layout {
+            KeyboardLayout::Full => &mut self.keyboard,
+            KeyboardLayout::Left => &mut self.keyboard_left,
+            KeyboardLayout::Right => &mut self.keyboard_right,
+        }
+    }
+
+    pub fn keys(&self, layout: KeyboardLayout, action: Action) -> &[KeyCode] {
+        self.layout(layout).get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+        self.gamepad.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn key_held(&self, keys: &ButtonInput<KeyCode>, layout: KeyboardLayout, action: Action) -> bool {
+        self.keys(layout, action).iter().any(|key| keys.pressed(*key) || keys.just_pressed(*key))
+    }
+
+    pub fn key_just_pressed(&self, keys: &ButtonInput<KeyCode>, layout: KeyboardLayout, action: Action) -> bool {
+        keys.any_just_pressed(self.keys(layout, action).iter().copied())
+    }
+
+    //menus open to everybody accept the keys of every layout
+    pub fn any_key_just_pressed(&self, keys: &ButtonInput<KeyCode>, action: Action) -> bool {
+        KeyboardLayout::ALL.iter().any(|layout| self.key_just_pressed(keys, *layout, action))
+    }
+
+    pub fn button_held(&self, buttons: &ButtonInput<GamepadButton>, gamepad: Gamepad, action: Action) -> bool {
+        self.buttons(action).iter().any(|button_type| {
+            let button = GamepadButton { gamepad, button_type: *button_type };
+            buttons.pressed(button) || buttons.just_pressed(button)
+        })
+    }
+
+    pub fn button_just_pressed(&self, buttons: &ButtonInput<GamepadButton>, gamepad: Gamepad, action: Action) -> bool {
+        buttons.any_just_pressed(
+            self.buttons(action).iter().map(|button_type| GamepadButton { gamepad, button_type: *button_type })
+        )
+    }
+
+    pub fn just_pressed(
+        &self,
+        controller: InputController,
+        keys: &ButtonInput<KeyCode>,
+        buttons: &ButtonInput<GamepadButton>,
+        action: Action,
+    ) -> bool {
+        match controller {
+            InputController::Keyboard(layout) => self.key_just_pressed(keys, layout, action),
+            InputController::Gamepad(gamepad) => self.button_just_pressed(buttons, gamepad, action),
+        }
+    }
+
+    //the combat buttons held on one layout of the keyboard
+    pub fn keyboard_frame(&self, keys: &ButtonInput<KeyCode>, layout: KeyboardLayout) -> InputFrame {
+        let mut frame = InputFrame::default();
+        for action in Action::ALL {
+            if let Some(button) = action.button() {
+                frame.set(button, self.key_held(keys, layout, action));
+            }
+        }
+        frame
+    }
+
+    //the left stick always moves the fighter, on top of the bound buttons
+    pub fn gamepad_frame(&self, buttons: &ButtonInput<GamepadButton>, axes: &Axis<GamepadAxis>, gamepad: Gamepad) -> InputFrame {
+        let stick = |axis_type| axes.get(GamepadAxis { gamepad, axis_type }).unwrap_or(0.);
+        let x = stick(GamepadAxisType::LeftStickX);
+        let y = stick(GamepadAxisType::LeftStickY);
+
+        let mut frame = InputFrame::default();
+        for action in Action::ALL {
+            let Some(button) = action.button() else {
+                continue;
+            };
+            let tilted = match action {
+                Action::Up => y > STICK_THRESHOLD,
+                Action::Down => y < -STICK_THRESHOLD,
+                Action::Back => x < -STICK_THRESHOLD,
+                Action::Forward => x > STICK_THRESHOLD,
+                _ => false,
+            };
+            frame.set(button, tilted || self.button_held(buttons, gamepad, action));
+        }
+        frame
+    }
+}
+
+//same folders the platforms use for the settings of other applications
+fn config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_no_conflicts<T: PartialEq + std::fmt::Debug>(bindings: &Bindings<T>) {
+        for action in Action::ALL {
+            let conflict = conflict(bindings, action);
+            assert!(conflict.is_none(), "{action:?} and {conflict:?} share an input");
+        }
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let controls = Controls::default();
+        for layout in KeyboardLayout::ALL {
+            assert_no_conflicts(controls.layout(layout));
+        }
+        assert_no_conflicts(&controls.gamepad);
+        assert_ne!(controls.buttons(Action::Fake), controls.buttons(Action::Confirm));
+    }
+
+    #[test]
+    fn parse_fills_missing_actions_with_defaults() {
+        let controls = Controls::parse("(keyboard: { Light: [KeyJ] }, gamepad: { Heavy: [South] })").unwrap();
+        let defaults = Controls::default();
+
+        assert_eq!(controls.keys(KeyboardLayout::Full, Action::Light), [KeyCode::KeyJ]);
+        assert_eq!(controls.keys(KeyboardLayout::Full, Action::Heavy), defaults.keys(KeyboardLayout::Full, Action::Heavy));
+        assert_eq!(controls.buttons(Action::Heavy), [GamepadButtonType::South]);
+        for action in Action::ALL {
+            assert!(!controls.keys(KeyboardLayout::Full, action).is_empty(), "{action:?} is unbound");
+            assert!(!controls.buttons(action).is_empty(), "{action:?} is unbound");
+            assert_eq!(controls.keys(KeyboardLayout::Left, action), defaults.keys(KeyboardLayout::Left, action));
+            assert_eq!(controls.keys(KeyboardLayout::Right, action), defaults.keys(KeyboardLayout::Right, action));
+        }
+    }
+
+    #[test]
+    fn parse_round_trips_saved_controls() {
+        let mut controls = Controls::default();
+        rebind(&mut controls.keyboard, Action::Parry, KeyCode::KeyP);
+        let text = ron::ser::to_string_pretty(&controls, ron::ser::PrettyConfig::default()).unwrap();
+        let parsed = Controls::parse(&text).unwrap();
+
+        assert_eq!(parsed.keyboard, controls.keyboard);
+        assert_eq!(parsed.gamepad, controls.gamepad);
+        assert!(Controls::parse("(keyboard: [").is_err());
+    }
+
+    #[test]
+    fn rebind_swaps_with_a_combat_action_using_the_input() {
+        let mut bindings = Controls::default().keyboard;
+        assert!(rebind(&mut bindings, Action::Light, KeyCode::KeyX));
+
+        assert_eq!(bindings[&Action::Light], [KeyCode::KeyX]);
+        assert_eq!(bindings[&Action::Heavy], [KeyCode::KeyZ]);
+        assert_no_conflicts(&bindings);
+    }
+
+    #[test]
+    fn rebind_lets_menu_and_combat_actions_share_an_input() {
+        let mut bindings = Controls::default().keyboard_left;
+        assert!(rebind(&mut bindings, Action::Cancel, KeyCode::KeyH));
+
+        assert_eq!(bindings[&Action::Cancel], [KeyCode::KeyH]);
+        assert_eq!(bindings[&Action::Fake], [KeyCode::KeyH]);
+    }
+
+    #[test]
+    fn rebind_swaps_between_menu_actions() {
+        let mut bindings = Controls::default().gamepad;
+        assert!(rebind(&mut bindings, Action::Confirm, GamepadButtonType::South));
+
+        assert_eq!(bindings[&Action::Confirm], [GamepadButtonType::South]);
+        assert_eq!(bindings[&Action::Cancel], [GamepadButtonType::East]);
+    }
+
+    #[test]
+    fn rebind_swaps_menu_actions_with_directions() {
+        let mut bindings = Controls::default().keyboard;
+        assert!(rebind(&mut bindings, Action::Confirm, KeyCode::KeyS));
+
+        assert_eq!(bindings[&Action::Confirm], [KeyCode::KeyS]);
+        assert_eq!(bindings[&Action::Down], [KeyCode::Enter]);
+        assert_no_conflicts(&bindings);
+    }
+
+    #[test]
+    fn rebind_swaps_fight_actions_read_outside_combat() {
+        let mut bindings = Controls::default().keyboard;
+        assert!(rebind(&mut bindings, Action::Start, KeyCode::KeyZ));
+        assert_eq!(bindings[&Action::Light], [KeyCode::Escape]);
+        assert_no_conflicts(&bindings);
+
+        assert!(rebind(&mut bindings, Action::Record, KeyCode::KeyX));
+        assert_eq!(bindings[&Action::Heavy], [KeyCode::KeyR]);
+        assert_no_conflicts(&bindings);
+    }
+
+    #[test]
+    fn rebind_is_rejected_when_the_swap_conflicts() {
+        //F is both light and confirm on the left half, down cannot take it
+        let mut bindings = Controls::default().keyboard_left;
+        let before = bindings.clone();
+        assert!(!rebind(&mut bindings, Action::Confirm, KeyCode::KeyS));
+        assert_eq!(bindings, before);
+    }
+}
@@ -0,0 +1,389 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{prelude::*, asset::LoadedFolder};
+
+use crate::{
+    components::*, create_characters, fighter_bundle, start_position, spawn_player_after_selection,
+    replay::record_inputs,
+    CharacterFolder, CombatSet, CombatStep, GameState, MatchSetup,
+};
+
+//an attack this close to touching the fighter is worth blocking
+const THREAT_MARGIN: f32 = 40.;
+
+
+//adds a computer controlled fighter to the match when the title asked for one
+pub struct CpuPlugin;
+
+impl Plugin for CpuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), spawn_cpu_fighter
+                .after(spawn_player_after_selection)
+                .run_if(resource_exists::<CpuOpponent>)
+                .in_set(MatchSetup)
+            )
+            //the cpu presses its buttons at the start of the game frame,
+            //so replays record them like the ones of a device
+            .add_systems(CombatStep, cpu_input
+                .before(record_inputs)
+                .before(CombatSet::Input)
+            );
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuLevel {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl CpuLevel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CpuLevel::Easy => "Easy",
+            CpuLevel::Normal => "Normal",
+            CpuLevel::Hard => "Hard",
+        }
+    }
+
+    pub fn next(&self) -> CpuLevel {
+        match self {
+            CpuLevel::Easy => CpuLevel::Normal,
+            CpuLevel::Normal | CpuLevel::Hard => CpuLevel::Hard,
+        }
+    }
+
+    pub fn prev(&self) -> CpuLevel {
+        match self {
+            CpuLevel::Easy | CpuLevel::Normal => CpuLevel::Easy,
+            CpuLevel::Hard => CpuLevel::Normal,
+        }
+    }
+
+    fn profile(&self) -> CpuProfile {
+        match self {
+            CpuLevel::Easy => CpuProfile { reaction_frames: 18, block_chance: 0.15, punish_chance: 0.1, aggression: 0.15 },
+            CpuLevel::Normal => CpuProfile { reaction_frames: 10, block_chance: 0.5, punish_chance: 0.5, aggression: 0.3 },
+            CpuLevel::Hard => CpuProfile { reaction_frames: 3, block_chance: 0.9, punish_chance: 0.95, aggression: 0.45 },
+        }
+    }
+}
+
+struct CpuProfile {
+    //game frames between two decisions
+    reaction_frames: u8,
+    //chances to block an incoming attack, to punish a recovering opponent
+    //and to attack when in range
+    block_chance: f32,
+    punish_chance: f32,
+    aggression: f32,
+}
+
+//the next match is played against the cpu
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct CpuOpponent(pub CpuLevel);
+
+//plays a fighter with the same buttons a person would press
+#[derive(Component)]
+pub struct CpuController {
+    level: CpuLevel,
+    rng: Rng,
+    //game frames left before the next decision
+    wait: u8,
+    //directions and block held until the next decision
+    held: InputFrame,
+}
+
+impl CpuController {
+    fn new(level: CpuLevel, rng: Rng) -> Self {
+        Self { level, rng, wait: 0, held: InputFrame::default() }
+    }
+}
+
+//xorshift, the cpu does not need anything better
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next() * n as f32) as usize).min(n.saturating_sub(1))
+    }
+}
+
+//an attack of the cpu fighter, with what is needed to choose it
+struct CpuAttack {
+    state: AnimationState,
+    startup: u8,
+    hitbox: Hitbox,
+}
+
+
+//the cpu picks a random character and joins after the players
+fn spawn_cpu_fighter(
+    mut commands: Commands,
+    opponent: Res<CpuOpponent>,
+    asset_server: Res<AssetServer>,
+    characters: Res<CharacterFolder>,
+    loaded_folders: Res<Assets<LoadedFolder>>,
+    loader: Res<Assets<Character>>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    mut fighters: Query<(&PlayerHandle, &mut Transform), With<Player>>,
+) {
+    let loaded_folder = loaded_folders.get(&characters.0).unwrap();
+    let pgs = create_characters(loaded_folder, loader);
+    let mut rng = Rng::from_time();
+    let Some((character_id, character)) = pgs.get(rng.below(pgs.len())) else {
+        return;
+    };
+
+    //everybody is spread again to make room for one more fighter
+    let handle = fighters.iter().count();
+    let players = handle + 1;
+    for (fighter_handle, mut transform) in fighters.iter_mut() {
+        transform.translation.x = start_position(fighter_handle.0, players);
+    }
+
+    let x = start_position(handle, players);
+    commands.spawn((
+        fighter_bundle(character, *character_id, handle, x, &asset_server, &mut texture_atlas_layouts),
+        CpuController::new(opponent.0, rng),
+    ));
+}
+
+type CpuFighter = (
+    Entity, &'static Transform, &'static Facing, &'static PlayerAnimationManagement,
+    &'static Hurtbox, Option<&'static Hurtboxes>, Has<HitStun>, Has<BlockStun>, Has<HitStop>
+);
+
+//every few frames looks at the closest opponent and decides what to hold and press
+pub fn cpu_input(
+    mut cpus: Query<(Entity, &mut CpuController, &mut PlayerInput)>,
+    fighters: Query<CpuFighter, With<Player>>,
+) {
+    for (entity, mut cpu, mut input) in cpus.iter_mut() {
+        let Ok((_, transform, facing, animation, hurtbox, hurtboxes, in_hitstun, in_blockstun, in_hitstop)) = fighters.get(entity) else {
+            continue;
+        };
+        let x = transform.translation.x;
+        let opponent = fighters.iter()
+            .filter(|(other, ..)| *other != entity)
+            .min_by(|(_, a, ..), (_, b, ..)| (a.translation.x - x).abs().total_cmp(&(b.translation.x - x).abs()));
+        let Some((_, opponent_transform, opponent_facing, opponent_animation, opponent_hurtbox, opponent_hurtboxes, ..)) = opponent else {
+            input.current = InputFrame::default();
+            continue;
+        };
+
+        //attack buttons are only pressed for a single frame
+        input.current = cpu.held;
+        //until the next decision, or while stunned, the same buttons stay held
+        if in_hitstun || in_blockstun || in_hitstop || cpu.wait > 0 {
+            cpu.wait = cpu.wait.saturating_sub(1);
+            continue;
+        }
+        let profile = cpu.level.profile();
+        cpu.wait = profile.reaction_frames;
+
+        let own_hurtboxes = hurtboxes.map_or_else(|| vec![hurtbox.clone()], |boxes| boxes.0.clone());
+        let opponent_hurtboxes = opponent_hurtboxes.map_or_else(|| vec![opponent_hurtbox.clone()], |boxes| boxes.0.clone());
+        let opponent_x = opponent_transform.translation.x;
+        let forward = if opponent_x >= x { InputButton::Right } else { InputButton::Left };
+        let backward = if forward == InputButton::Right { InputButton::Left } else { InputButton::Right };
+
+        let attacks = attacks(animation);
+        let in_range: Vec<&CpuAttack> = attacks.iter()
+            .filter(|attack| reaches(&attack.hitbox, x, *facing, &opponent_hurtboxes, opponent_x, *opponent_facing, 0.))
+            .collect();
+
+        let (opponent_manager, opponent_attack) = opponent_animation.get_current_animation();
+        let mut held = InputFrame::default();
+        let mut attack = None;
+
+        let opponent_attack = opponent_attack.as_ref().filter(|_| opponent_animation.state.is_attack());
+        let threatening = opponent_attack.is_some_and(|opponent_attack| {
+            (opponent_manager.is_within_startup() || opponent_manager.is_within_active())
+                && reaches(&opponent_attack.hitbox, opponent_x, *opponent_facing, &own_hurtboxes, x, *facing, THREAT_MARGIN)
+        });
+        let recovering = opponent_attack.is_some() && opponent_manager.is_within_recovery();
+
+        //an attack that cannot touch, like a whiff, is played around like no attack
+        if threatening {
+            //block the attack coming
+            held.set(InputButton::Block, cpu.rng.next() < profile.block_chance);
+        } else if recovering {
+            if cpu.rng.next() < profile.punish_chance {
+                //the fastest attack that lands before the opponent can act again
+                let remaining = opponent_manager.remaining_frames();
+                attack = in_range.iter()
+                    .filter(|attack| (attack.startup as u16) < remaining)
+                    .min_by_key(|attack| attack.startup)
+                    .map(|attack| attack.state.clone());
+            }
+        } else if in_range.is_empty() {
+            held.set(forward, true);
+        } else if cpu.rng.next() < profile.aggression {
+            let pick = cpu.rng.below(in_range.len());
+            attack = Some(in_range[pick].state.clone());
+        } else if cpu.rng.next() < 0.2 {
+            //steps back from time to time instead of standing still
+            held.set(backward, true);
+        }
+
+        cpu.held = held;
+        input.current = held;
+        if let Some(state) = attack {
+            press_attack(&mut input.current, &state, forward);
+        }
+    }
+}
+
+//grounded attacks of the fighter, the up variants would also jump
+fn attacks(animation: &PlayerAnimationManagement) -> Vec<CpuAttack> {
+    let mut attacks: Vec<CpuAttack> = animation.animations.iter()
+        .filter(|(state, _)| !matches!(
+            state,
+            AnimationState::LightAttack(AttackDirection::Up) | AnimationState::HeavyAttack(AttackDirection::Up)
+        ))
+        .filter_map(|(state, (manager, attack))| {
+            let attack = attack.as_ref()?;
+            let startup = manager.options()?.startup_frames;
+            Some(CpuAttack { state: state.clone(), startup, hitbox: attack.hitbox.clone() })
+        })
+        .collect();
+    //the map has no order, the choices must not depend on it
+    attacks.sort_by_key(|attack| (attack.startup, format!("{:?}", attack.state)));
+    attacks
+}
+
+//whether a hitbox started from `x` would touch any of the hurtboxes of the target
+fn reaches(
+    hitbox: &Hitbox, x: f32, facing: Facing,
+    hurtboxes: &[Hurtbox], target_x: f32, target_facing: Facing,
+    margin: f32,
+) -> bool {
+    let center = x + hitbox.offset(facing).x;
+    hurtboxes.iter().any(|hurtbox| {
+        let target = target_x + hurtbox.offset(target_facing).x;
+        (center - target).abs() <= (hitbox.length + hurtbox.length) / 2. + margin
+    })
+}
+
+//the buttons a person would press to start the attack
+fn press_attack(frame: &mut InputFrame, state: &AnimationState, forward: InputButton) {
+    let (button, direction) = match state {
+        AnimationState::LightAttack(direction) => (InputButton::Light, direction),
+        AnimationState::HeavyAttack(direction) => (InputButton::Heavy, direction),
+        _ => return,
+    };
+    frame.set(button, true);
+    match direction {
+        AttackDirection::Forward => frame.set(forward, true),
+        AttackDirection::Down => frame.set(InputButton::Down, true),
+        AttackDirection::Normal | AttackDirection::Up => {},
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hitbox(x: f32, length: f32) -> Hitbox {
+        Hitbox { x, y: 0., length, height: 10. }
+    }
+
+    fn hurtbox(x: f32, length: f32) -> Hurtbox {
+        Hurtbox { x, y: 0., length, height: 10. }
+    }
+
+    #[test]
+    fn rng_stays_in_range() {
+        let mut rng = Rng(12345);
+        for _ in 0..1000 {
+            let value = rng.next();
+            assert!((0. ..1.).contains(&value));
+            assert!(rng.below(3) < 3);
+        }
+        assert_eq!(rng.below(1), 0);
+        assert_eq!(rng.below(0), 0);
+    }
+
+    #[test]
+    fn rng_repeats_with_the_same_seed() {
+        let (mut a, mut b) = (Rng(99), Rng(99));
+        for _ in 0..10 {
+            assert_eq!(a.next(), b.next());
+        }
+        assert_ne!(Rng(0x1234_5678_9abc_def1).next(), Rng(0x0fed_cba9_8765_4321).next());
+    }
+
+    #[test]
+    fn reaches_depends_on_distance_and_facing() {
+        //a hitbox 20 wide, 30 in front of the attacker standing at 0
+        let hitbox = hitbox(30., 20.);
+        let target = [hurtbox(0., 20.)];
+
+        assert!(reaches(&hitbox, 0., Facing::Right, &target, 50., Facing::Left, 0.));
+        assert!(!reaches(&hitbox, 0., Facing::Right, &target, 61., Facing::Left, 0.));
+        //the margin counts as extra reach
+        assert!(reaches(&hitbox, 0., Facing::Right, &target, 61., Facing::Left, 11.));
+        //turned around the hitbox is on the other side
+        assert!(!reaches(&hitbox, 0., Facing::Left, &target, 50., Facing::Left, 0.));
+        assert!(reaches(&hitbox, 0., Facing::Left, &target, -50., Facing::Right, 0.));
+    }
+
+    #[test]
+    fn reaches_any_hurtbox() {
+        let hitbox = hitbox(0., 10.);
+        let target = [hurtbox(-100., 10.), hurtbox(100., 10.)];
+        assert!(reaches(&hitbox, 100., Facing::Right, &target, 0., Facing::Right, 0.));
+        assert!(!reaches(&hitbox, 50., Facing::Right, &target, 0., Facing::Right, 0.));
+        assert!(!reaches(&hitbox, 0., Facing::Right, &[], 0., Facing::Right, 0.));
+    }
+
+    #[test]
+    fn press_attack_uses_the_button_and_direction() {
+        let mut frame = InputFrame::default();
+        press_attack(&mut frame, &AnimationState::HeavyAttack(AttackDirection::Forward), InputButton::Left);
+        assert!(frame.pressed(InputButton::Heavy));
+        assert!(frame.pressed(InputButton::Left));
+        assert!(!frame.pressed(InputButton::Light));
+
+        let mut frame = InputFrame::default();
+        press_attack(&mut frame, &AnimationState::LightAttack(AttackDirection::Down), InputButton::Right);
+        assert!(frame.pressed(InputButton::Light));
+        assert!(frame.pressed(InputButton::Down));
+        assert!(!frame.pressed(InputButton::Right));
+
+        let mut frame = InputFrame::default();
+        press_attack(&mut frame, &AnimationState::LightAttack(AttackDirection::Normal), InputButton::Right);
+        assert_eq!(frame, {
+            let mut light = InputFrame::default();
+            light.set(InputButton::Light, true);
+            light
+        });
+    }
+
+    #[test]
+    fn press_attack_ignores_other_states() {
+        let mut frame = InputFrame::default();
+        press_attack(&mut frame, &AnimationState::Idle, InputButton::Right);
+        assert_eq!(frame, InputFrame::default());
+    }
+}
@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 
-use crate::components::{Hitbox, Hurtbox};
+use crate::components::{Facing, Hitboxes, Hurtboxes, Pushbox};
 
 
 fn in_debug(debug: bool) -> impl Condition<()> {
@@ -19,6 +19,7 @@ impl Plugin for DebugPlugin {
         app.add_systems(Update, (
                     debug_hitbox,
                     debug_hurtbox,
+                    debug_pushbox,
                 ).run_if(in_debug(self.hitbox))
            );
         if self.inspector {
@@ -29,20 +30,34 @@ impl Plugin for DebugPlugin {
 
 fn debug_hitbox(
     mut gizmos: Gizmos,
-    query: Query<(&Hitbox, &Transform)>
+    query: Query<(&Hitboxes, &Transform, &Facing)>
 ) {
-    for (hitbox, transform) in &query {
-        let pos = Vec2::new(transform.translation.x, transform.translation.y) + Vec2::new(hitbox.x, hitbox.y);
-        gizmos.rect_2d(pos, 0., Vec2::new(hitbox.length, hitbox.height), Color::RED);
+    for (hitboxes, transform, facing) in &query {
+        for hitbox in hitboxes.0.iter() {
+            let pos = Vec2::new(transform.translation.x, transform.translation.y) + hitbox.offset(*facing);
+            gizmos.rect_2d(pos, 0., hitbox.size(), Color::RED);
+        }
     }
 }
 
 fn debug_hurtbox(
     mut gizmos: Gizmos,
-    query: Query<(&Hurtbox, &Transform)>
+    query: Query<(&Hurtboxes, &Transform, &Facing)>
+) {
+    for (hurtboxes, transform, facing) in &query {
+        for hurtbox in hurtboxes.0.iter() {
+            let pos = Vec2::new(transform.translation.x, transform.translation.y) + hurtbox.offset(*facing);
+            gizmos.rect_2d(pos, 0., hurtbox.size(), Color::GREEN);
+        }
+    }
+}
+
+fn debug_pushbox(
+    mut gizmos: Gizmos,
+    query: Query<(&Pushbox, &Transform, &Facing)>
 ) {
-    for (hitbox, transform) in &query {
-        let pos = Vec2::new(transform.translation.x, transform.translation.y) + Vec2::new(hitbox.x, hitbox.y);
-        gizmos.rect_2d(pos, 0., Vec2::new(hitbox.length, hitbox.height), Color::GREEN);
+    for (pushbox, transform, facing) in &query {
+        let pos = Vec2::new(transform.translation.x, transform.translation.y) + pushbox.offset(*facing);
+        gizmos.rect_2d(pos, 0., pushbox.size(), Color::BLUE);
     }
 }
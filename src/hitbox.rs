@@ -1,46 +1,95 @@
 use bevy::math::bounding::{Aabb2d, IntersectsVolume};
 use bevy::prelude::*;
 
-use crate::{components::*, GameState};
+use crate::{components::*, CombatSet, CombatStep};
 
+//upward speed given by hits without a launch to a victim already in the air
+const AIR_HIT_LIFT: f32 = 3.;
 
-pub struct HitManagementPlugin;
+
+pub struct HitManagementPlugin {
+    //percentage of damage dealt by each hit of a combo,
+    //the last value is used for every hit after it
+    pub damage_scaling: Vec<u32>,
+    //hits an airborne fighter can take before it cannot be hit until it lands
+    pub juggle_limit: u8,
+}
+
+impl Default for HitManagementPlugin {
+    fn default() -> Self {
+        Self { damage_scaling: vec![100, 100, 80, 70, 60, 50, 40, 30], juggle_limit: 3 }
+    }
+}
 
 impl Plugin for HitManagementPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (
+        app.insert_resource(DamageScaling(self.damage_scaling.clone()))
+            .insert_resource(JuggleLimit(self.juggle_limit))
+            .add_systems(CombatStep, (
+                check_hitstop,
+                check_hitstun,
+                check_blockstun,
                 manage_hitboxes,
-                check_hitboxes,
                 check_hits,
-                check_hitstun
-            ).run_if(in_state(GameState::InGame))
-        ).add_event::<HitEvent>();
+                check_hitboxes,
+            ).chain().in_set(CombatSet::Hits)
+        ).add_event::<HitEvent>()
+        .add_event::<ParryEvent>();
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct DamageScaling(pub Vec<u32>);
+
+impl DamageScaling {
+    //damage of a hit after `hits` hits of the same combo
+    pub fn scale(&self, damage: u32, hits: u16) -> u32 {
+        let percent = match self.0.get(hits as usize) {
+            Some(percent) => *percent,
+            None => self.0.last().copied().unwrap_or(100),
+        };
+        damage * percent / 100
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct JuggleLimit(pub u8);
+
+impl JuggleLimit {
+    //past the limit the victim falls down untouched
+    pub fn allows(&self, juggle: Option<&Juggle>) -> bool {
+        !juggle.is_some_and(|juggle| juggle.hits >= self.0)
     }
 }
 
+pub fn collider(transform: &Transform, offset: Vec2, size: Vec2) -> Aabb2d {
+    Aabb2d::new(transform.translation.truncate() + offset, size / 2.)
+}
+
 pub fn check_hits(
-    mut target: Query<(Entity, &Hurtbox, &GlobalTransform)>,
-    source: Query<(Entity, &Hitbox, &Transform)>,
+    target: Query<(Entity, &Hurtboxes, &Transform, &Facing)>,
+    //a frozen attack cannot connect again until the hitstop is over
+    mut source: Query<(Entity, &Hitboxes, &Transform, &Facing, &PlayerAnimationManagement, &mut AttackHits), Without<HitStop>>,
     mut ev_collision: EventWriter<HitEvent>,
 ) {
-    for (source_entity, source_trigger, source_transform) in source.iter() {
-        let position = 
-            Vec2::new(source_transform.translation.x, source_transform.translation.y)
-            +
-            Vec2::new(source_trigger.x, source_trigger.y);
-        let size = Vec2::new(source_trigger.x, source_trigger.y);
-        let first_collider = Aabb2d::new(position, size);
-
-        for (target_entity, target_trigger, target_transform) in target.iter_mut() {
-            let position = 
-                Vec2::new(target_transform.compute_transform().translation.x, target_transform.compute_transform().translation.y)
-                +
-                Vec2::new(source_trigger.x, source_trigger.y);
- 
-            let size = Vec2::new(target_trigger.x, target_trigger.y);
-            let second_collider = Aabb2d::new(position, size);
-
-            if source_entity != target_entity && first_collider.intersects(&second_collider) {
+    for (source_entity, hitboxes, source_transform, source_facing, animation, mut attack_hits) in source.iter_mut() {
+        let Some(attack) = &animation.get_current_animation().1 else {
+            continue;
+        };
+        let first_colliders: Vec<Aabb2d> = hitboxes.0.iter()
+            .map(|hitbox| collider(source_transform, hitbox.offset(*source_facing), hitbox.size()))
+            .collect();
+
+        for (target_entity, hurtboxes, target_transform, target_facing) in target.iter() {
+            //any hitbox touching any hurtbox is a hit
+            let overlapping = hurtboxes.0.iter()
+                .map(|hurtbox| collider(target_transform, hurtbox.offset(*target_facing), hurtbox.size()))
+                .any(|second_collider| first_colliders.iter().any(|first_collider| first_collider.intersects(&second_collider)));
+
+            if source_entity != target_entity
+                && overlapping
+                && attack_hits.can_hit(target_entity, attack.max_hits()) {
+                attack_hits.register(target_entity, attack.rehit_frames());
                 ev_collision.send(HitEvent { 
                     target: target_entity,
                     source: source_entity,
@@ -50,22 +99,43 @@ pub fn check_hits(
     }
 }
 
+//picks the boxes of the current frame of the animation,
+//falling back to the character hurtbox and the attack hitbox
 pub fn manage_hitboxes (
     mut commands: Commands,
-    query: Query<(Entity, &PlayerAnimationManagement)>,
+    mut query: Query<(Entity, &PlayerAnimationManagement, &Hurtbox, Option<&mut Hurtboxes>, Option<&mut AttackHits>, Has<HitStop>)>,
 ) {
-    for (entity, player_animation) in &query {
+    for (entity, player_animation, hurtbox, hurtboxes, attack_hits, in_hitstop) in &mut query {
         let (anim, attack) = &player_animation.get_current_animation();
+        let frame_boxes = anim.current_boxes();
+
+        let current_hurtboxes = frame_boxes
+            .and_then(|boxes| boxes.hurtboxes.clone())
+            .unwrap_or_else(|| vec![hurtbox.clone()]);
+        match hurtboxes {
+            Some(mut hurtboxes) => hurtboxes.0 = current_hurtboxes,
+            None => { commands.entity(entity).insert(Hurtboxes(current_hurtboxes)); },
+        }
+
         if anim.is_within_active() {
             //if is still runing then check for hitboxes
             //spawn hitbox
             if let Some(attack) = attack.clone() {
-                //add attack to trigger or just check what type of attack active in other
-                //player?
-                commands.entity(entity).insert(attack.hitbox);
+                let current_hitboxes = frame_boxes
+                    .and_then(|boxes| boxes.hitboxes.clone())
+                    .unwrap_or_else(|| vec![attack.hitbox]);
+                commands.entity(entity).insert(Hitboxes(current_hitboxes));
+                //every activation starts with nobody hit,
+                //the rehit cooldown is frozen with the attack during the hitstop
+                match attack_hits {
+                    Some(mut attack_hits) => if !in_hitstop {
+                        attack_hits.tick();
+                    },
+                    None => { commands.entity(entity).insert(AttackHits::default()); },
+                }
             }
         } else {
-            commands.entity(entity).remove::<Hitbox>();
+            commands.entity(entity).remove::<(Hitboxes, AttackHits)>();
         }
     }
 }
@@ -73,30 +143,269 @@ pub fn manage_hitboxes (
 
 pub fn check_hitboxes (
     mut commands: Commands,
-    mut query: Query<(Entity, &mut Health, &PlayerAnimationManagement)>,
+    scaling: Res<DamageScaling>,
+    juggle_limit: Res<JuggleLimit>,
+    mut query: Query<(
+        &mut Health, &mut PlayerAnimationManagement, Has<IsBlocking>, Has<HitStun>, Option<&mut Combo>,
+        &mut Velocity, &Transform, &Facing, Option<&mut Juggle>
+    )>,
     mut ev_collision: EventReader<HitEvent>,
+    mut ev_parry: EventWriter<ParryEvent>,
 ) {
     for ev in ev_collision.read() {
-        if let Some((entity, mut hp, player_aniation)) = query.iter_mut().find(|(entity, _, _)| &ev.target == entity) {
-            let attack = player_aniation.get_current_animation().1.clone().unwrap();
+        let Ok([
+            (_, mut attacker, _, _, _, _, _, attacker_facing, _),
+            (mut hp, mut defender, is_blocking, in_hitstun, combo, mut velocity, transform, _, juggle),
+        ]) = query.get_many_mut([ev.source, ev.target]) else {
+            continue;
+        };
+        if !juggle_limit.allows(juggle.as_deref()) {
+            continue;
+        }
+        let direction = attacker_facing.sign();
+        //the attack comes from whoever owns the hitbox
+        let Some(attack) = attacker.get_current_animation().1.clone() else {
+            continue;
+        };
+
+        if attack.hitstop_frames > 0 {
+            commands.entity(ev.source).insert(HitStop::new(attack.hitstop_frames));
+            commands.entity(ev.target).insert(HitStop::new(attack.hitstop_frames));
+        }
 
-            hp.damage(attack.damage);
+        if defender.is_parrying() {
+            //the attacker loses what is left of the attack and can be punished,
+            //while the defender can act right away
+            let punish_frames = attacker.get_current_animation().0.remaining_frames();
+            attacker.interrupt();
+            defender.interrupt();
+
+            commands.entity(ev.source)
+                .remove::<Hitboxes>()
+                .insert(HitStun::new(punish_frames.min(u8::MAX.into()) as u8));
+            ev_parry.send(ParryEvent {
+                defender: ev.target,
+                attacker: ev.source,
+            });
+        } else if is_blocking {
+            //the shield only lets the chip damage through
+            hp.damage(attack.chip_damage);
+            velocity.x = attack.knockback.pushback * direction;
+
+            commands.entity(ev.target)
+                .insert(BlockStun::new(attack.block_stun_frames));
+        } else {
+            //a hit landed before the hitstun of the previous one ends continues the combo
+            let combo = combo.filter(|_| in_hitstun);
+            let hits = combo.as_ref().map_or(0, |combo| combo.hits);
+            let damage = scaling.scale(attack.damage, hits);
+            hp.damage(damage);
+
+            match combo {
+                Some(mut combo) => combo.add(damage),
+                None => { commands.entity(ev.target).insert(Combo::new(damage)); },
+            }
 
-            commands.entity(entity)
-                .remove::<Hitbox>()
-                .insert(HitStun::new(attack.hit_stun_frames, MAX_FRAME_RATE));
+            let airborne = transform.translation.y > 0. || juggle.is_some();
+            match attack.knockback.launch {
+                Some(launch) => {
+                    velocity.x = launch.x * direction;
+                    velocity.y = launch.y;
+                },
+                //hits in the air keep the victim up
+                None if airborne => {
+                    velocity.x = attack.knockback.pushback * direction;
+                    velocity.y = AIR_HIT_LIFT;
+                },
+                None => velocity.x = attack.knockback.pushback * direction,
+            }
+            if attack.knockback.launch.is_some() || airborne {
+                match juggle {
+                    Some(mut juggle) => juggle.add(),
+                    None => { commands.entity(ev.target).insert(Juggle { hits: 1 }); },
+                }
+            }
+
+            commands.entity(ev.target)
+                .remove::<Hitboxes>()
+                .insert(HitStun::new(attack.hit_stun_frames));
+        }
+    }
+}
+
+//counts down the hitstop frames and removes it once finished
+pub fn check_hitstop(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut HitStop)>
+) {
+    for (entity, mut hitstop) in &mut query {
+        hitstop.tick();
+        if hitstop.is_finished() {
+            commands.entity(entity).remove::<HitStop>();
         }
     }
 }
 
-//if the hitstun timer is finished then it removes it
+//counts down the hitstun frames and removes it once finished,
+//the stun only starts once the hitstop is over and ends the combo with it
 pub fn check_hitstun(
     mut commands: Commands,
-    query: Query<(Entity, &HitStun)>
+    mut query: Query<(Entity, &mut HitStun), Without<HitStop>>
 ) {
-    for (entity, hitstun) in &query {
+    for (entity, mut hitstun) in &mut query {
+        hitstun.tick();
         if hitstun.is_finished() {
-            commands.entity(entity).remove::<HitStun>();
+            commands.entity(entity).remove::<(HitStun, Combo)>();
         }
     }
 }
+
+//counts down the blockstun frames and removes it once finished
+pub fn check_blockstun(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut BlockStun), Without<HitStop>>
+) {
+    for (entity, mut blockstun) in &mut query {
+        blockstun.tick();
+        if blockstun.is_finished() {
+            commands.entity(entity).remove::<BlockStun>();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn damage_scaling_follows_the_table() {
+        let scaling = DamageScaling(vec![100, 80, 50]);
+        assert_eq!(scaling.scale(20, 0), 20);
+        assert_eq!(scaling.scale(20, 1), 16);
+        assert_eq!(scaling.scale(20, 2), 10);
+        //the last value is kept for every hit after it
+        assert_eq!(scaling.scale(20, 3), 10);
+        assert_eq!(scaling.scale(20, u16::MAX), 10);
+    }
+
+    #[test]
+    fn damage_scaling_without_table_keeps_the_damage() {
+        assert_eq!(DamageScaling(Vec::new()).scale(35, 4), 35);
+    }
+
+    #[test]
+    fn scaled_damage_rounds_down() {
+        assert_eq!(DamageScaling(vec![70]).scale(5, 0), 3);
+    }
+
+    #[test]
+    fn juggle_limit_stops_hits_once_reached() {
+        let limit = JuggleLimit(3);
+        assert!(limit.allows(None));
+
+        let mut juggle = Juggle { hits: 1 };
+        assert!(limit.allows(Some(&juggle)));
+        juggle.add();
+        assert!(limit.allows(Some(&juggle)));
+        juggle.add();
+        assert!(!limit.allows(Some(&juggle)));
+    }
+
+    #[test]
+    fn no_juggle_limit_blocks_every_air_hit() {
+        assert!(JuggleLimit(0).allows(None));
+        assert!(!JuggleLimit(0).allows(Some(&Juggle { hits: 0 })));
+    }
+
+    fn fighter(attack: Option<Attack>) -> impl Bundle {
+        let animation = Animation { indexes: vec![0], fps: 1, options: None, boxes: Vec::new() };
+        let mut animations = HashMap::new();
+        animations.insert(AnimationState::Idle, (AnimationManager::new(animation), None));
+        let mut management = PlayerAnimationManagement::new(animations);
+        if let Some(attack) = attack {
+            let state = AnimationState::LightAttack(AttackDirection::Normal);
+            management.animations.insert(state.clone(), (AnimationManager::new(attack.animation.clone()), Some(attack)));
+            management.state = state;
+        }
+        (management, Health::new(100), Velocity::default(), Transform::default(), Facing::Right)
+    }
+
+    fn attack() -> Attack {
+        ron::from_str("(
+            damage: 20,
+            hit_stun_frames: 2,
+            hitbox: ( x: 0., y: 0., length: 10., height: 10. ),
+            animation: ( fps: 1, indexes: [0, 0, 0], options: Some(( startup_frames: 0, active_frames: 3, recovery_frames: 0 )) ),
+        )").unwrap()
+    }
+
+    #[test]
+    fn rehit_cooldown_waits_for_the_hitstop() {
+        let mut world = World::new();
+        let target = world.spawn_empty().id();
+        let mut attack_hits = AttackHits::default();
+        attack_hits.register(target, 2);
+        let attacker = world.spawn((
+            fighter(Some(attack())),
+            Hurtbox { x: 0., y: 0., length: 10., height: 10. },
+            attack_hits,
+            HitStop::new(5),
+        )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(manage_hitboxes);
+        let can_hit = |world: &World| world.get::<AttackHits>(attacker).unwrap().can_hit(target, 3);
+
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert!(!can_hit(&world));
+
+        world.entity_mut(attacker).remove::<HitStop>();
+        schedule.run(&mut world);
+        assert!(!can_hit(&world));
+        schedule.run(&mut world);
+        assert!(can_hit(&world));
+    }
+
+    #[test]
+    fn a_new_hitstun_does_not_continue_a_finished_combo() {
+        let attack = attack();
+
+        let mut world = World::new();
+        world.insert_resource(DamageScaling(vec![100, 50]));
+        world.insert_resource(JuggleLimit(3));
+        world.init_resource::<Events<HitEvent>>();
+        world.init_resource::<Events<ParryEvent>>();
+        let attacker = world.spawn(fighter(Some(attack))).id();
+        let defender = world.spawn(fighter(None)).id();
+
+        let mut hits = Schedule::default();
+        hits.add_systems(check_hitboxes);
+        let mut stun = Schedule::default();
+        stun.add_systems(check_hitstun);
+        let mut hit = |world: &mut World| {
+            world.send_event(HitEvent { target: defender, source: attacker });
+            hits.run(world);
+        };
+
+        hit(&mut world);
+        assert_eq!(world.get::<Health>(defender).unwrap().value(), 80);
+        hit(&mut world);
+        assert_eq!(world.get::<Health>(defender).unwrap().value(), 70);
+        assert_eq!(world.get::<Combo>(defender).unwrap().hits, 2);
+
+        stun.run(&mut world);
+        stun.run(&mut world);
+        assert!(world.get::<HitStun>(defender).is_none());
+        assert!(world.get::<Combo>(defender).is_none());
+
+        //like the stun of a punished attack after a parry
+        world.entity_mut(defender).insert(HitStun::new(10));
+        hit(&mut world);
+        assert_eq!(world.get::<Health>(defender).unwrap().value(), 50);
+        assert_eq!(world.get::<Combo>(defender).unwrap().hits, 1);
+    }
+}
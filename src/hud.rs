@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+
+use crate::{components::*, round::MatchUi, GameState};
+
+const BAR_WIDTH: f32 = 40.;
+const BAR_HEIGHT: f32 = 24.;
+//seconds the recent damage stays before it starts to drain
+const TRAIL_DELAY: f32 = 0.6;
+//fraction of the whole bar drained every second
+const TRAIL_SPEED: f32 = 0.5;
+//seconds the counter stays after the combo is over
+const COMBO_LINGER: f32 = 1.5;
+
+const HEALTH_COLOR: Color = Color::rgb(0.95, 0.8, 0.1);
+const TRAIL_COLOR: Color = Color::rgb(0.85, 0.1, 0.1);
+const BACKGROUND_COLOR: Color = Color::rgba(0., 0., 0., 0.7);
+
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (spawn_health_bars, update_health_bars)
+            .chain()
+            .run_if(in_state(GameState::InGame))
+        );
+    }
+}
+
+
+//a bar follows the health of one fighter
+#[derive(Component)]
+pub struct HealthBar {
+    fighter: Entity,
+    fill: Entity,
+    trail: Entity,
+    combo: Entity,
+    //ratio of health shown last frame
+    health: f32,
+    //ratio the recent damage segment reaches
+    trail_health: f32,
+    trail_delay: f32,
+    combo_linger: f32,
+}
+
+
+//bars are built once, when the fighters join the match
+fn spawn_health_bars(
+    mut commands: Commands,
+    fighters: Query<(Entity, &PlayerHandle, &Name), (With<Player>, Added<Health>)>,
+) {
+    for (fighter, handle, name) in fighters.iter() {
+        //even players on the left, odd players on the right,
+        //both draining towards the center of the screen
+        let left_side = handle.0 % 2 == 0;
+        let row = (handle.0 / 2) as f32;
+        let side = |style: &mut Style, value: Val| if left_side {
+            style.left = value;
+        } else {
+            style.right = value;
+        };
+
+        let mut bar_style = Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(80. + row * (BAR_HEIGHT + 40.)),
+            width: Val::Percent(BAR_WIDTH),
+            height: Val::Px(BAR_HEIGHT),
+            ..default()
+        };
+        side(&mut bar_style, Val::Percent(2.));
+
+        let mut segment_style = Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            ..default()
+        };
+        side(&mut segment_style, Val::Px(0.));
+
+        let mut name_style = Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(100.),
+            ..default()
+        };
+        side(&mut name_style, Val::Px(0.));
+
+        let mut combo_style = Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(100.),
+            ..default()
+        };
+        side(&mut combo_style, Val::Px(0.));
+
+        let mut trail = Entity::PLACEHOLDER;
+        let mut fill = Entity::PLACEHOLDER;
+        let mut combo = Entity::PLACEHOLDER;
+        let bar = commands.spawn((
+                MatchUi,
+                NodeBundle {
+                    style: bar_style,
+                    background_color: BackgroundColor(BACKGROUND_COLOR),
+                    ..default()
+                },
+            ))
+            .with_children(|parent| {
+                trail = parent.spawn(NodeBundle {
+                    style: segment_style.clone(),
+                    background_color: BackgroundColor(TRAIL_COLOR),
+                    ..default()
+                }).id();
+                fill = parent.spawn(NodeBundle {
+                    style: segment_style,
+                    background_color: BackgroundColor(HEALTH_COLOR),
+                    ..default()
+                }).id();
+                parent.spawn(TextBundle::from_section(name, TextStyle {
+                    font_size: 24.,
+                    ..default()
+                }).with_style(name_style));
+                combo = parent.spawn(TextBundle::from_section("", TextStyle {
+                    font_size: 30.,
+                    color: HEALTH_COLOR,
+                    ..default()
+                }).with_style(combo_style)).id();
+            })
+            .id();
+
+        commands.entity(bar).insert(HealthBar {
+            fighter,
+            fill,
+            trail,
+            combo,
+            health: 1.,
+            trail_health: 1.,
+            trail_delay: 0.,
+            combo_linger: 0.,
+        });
+    }
+}
+
+fn update_health_bars(
+    time: Res<Time>,
+    fighters: Query<(&Health, Option<&Combo>, Has<HitStun>), With<Player>>,
+    mut bars: Query<&mut HealthBar>,
+    mut styles: Query<&mut Style>,
+    mut texts: Query<&mut Text>,
+) {
+    for mut bar in bars.iter_mut() {
+        let Ok((health, combo, in_hitstun)) = fighters.get(bar.fighter) else {
+            continue;
+        };
+        let ratio = health.value() as f32 / health.max().max(1) as f32;
+
+        if ratio < bar.health {
+            //every new hit keeps the damage on screen a bit longer
+            bar.trail_delay = TRAIL_DELAY;
+        } else if ratio > bar.trail_health {
+            bar.trail_health = ratio;
+        }
+        bar.health = ratio;
+
+        if bar.trail_delay > 0. {
+            bar.trail_delay -= time.delta_seconds();
+        } else {
+            bar.trail_health = (bar.trail_health - TRAIL_SPEED * time.delta_seconds()).max(ratio);
+        }
+
+        if let Ok(mut style) = styles.get_mut(bar.fill) {
+            style.width = Val::Percent(ratio * 100.);
+        }
+        if let Ok(mut style) = styles.get_mut(bar.trail) {
+            style.width = Val::Percent(bar.trail_health * 100.);
+        }
+
+        //the counter shows the combo the fighter is taking, from the second hit
+        let Ok(mut text) = texts.get_mut(bar.combo) else {
+            continue;
+        };
+        match combo {
+            Some(combo) if in_hitstun && combo.hits > 1 => {
+                text.sections[0].value = format!("{} hits  {} damage", combo.hits, combo.damage);
+                bar.combo_linger = COMBO_LINGER;
+            },
+            _ if bar.combo_linger > 0. => bar.combo_linger -= time.delta_seconds(),
+            _ => text.sections[0].value.clear(),
+        }
+    }
+}
@@ -0,0 +1,2 @@
+//types shared by the game and the tools in src/bin
+pub mod components;
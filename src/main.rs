@@ -1,22 +1,42 @@
-use std::collections::HashMap;
-
-use bevy::{prelude::*, window::{EnabledButtons, WindowResolution}, asset::LoadedFolder};
+use bevy::{prelude::*, window::{EnabledButtons, WindowResolution}, asset::LoadedFolder, input::InputSystem, ecs::schedule::ScheduleLabel};
 use bevy_common_assets::ron::RonAssetPlugin;
 
 
 mod systems;
-mod components;
 mod debug;
 mod title_screen;
 mod character_selection;
 mod hitbox;
-
+mod rollback;
+mod stage;
+mod round;
+mod pause;
+mod hud;
+mod reload;
+mod replay;
+mod controls;
+mod options;
+mod cpu;
+mod training;
+
+use smash::components;
 use character_selection::{CharacterSelectionPlugin, SelectedCharacter};
 use systems::*;
 use components::*;
 use debug::*;
 use title_screen::*;
 use hitbox::*;
+use rollback::*;
+use stage::*;
+use round::*;
+use pause::*;
+use hud::*;
+use reload::*;
+use replay::*;
+use controls::*;
+use options::*;
+use cpu::*;
+use training::*;
 
 const MAX_WINDOW_HEIGHT: f32 = 300.;
 const MAX_WINDOW_WIDTH: f32 = 300.;
@@ -43,9 +63,20 @@ fn main() {
             })
             )
         .add_plugins(DebugPlugin { hitbox: true, inspector: true })
+        .add_plugins(ControlsPlugin)
         .add_plugins(TitleScreenPlugin)
+        .add_plugins(OptionsPlugin)
         .add_plugins(CharacterSelectionPlugin)
-        .add_plugins(HitManagementPlugin)
+        .add_plugins(HitManagementPlugin::default())
+        .add_plugins(RollbackPlugin)
+        .add_plugins(StagePlugin)
+        .add_plugins(RoundPlugin { rounds: 3, round_seconds: 99 })
+        .add_plugins(PausePlugin)
+        .add_plugins(HudPlugin)
+        .add_plugins(HotReloadPlugin)
+        .add_plugins(ReplayPlugin)
+        .add_plugins(CpuPlugin)
+        .add_plugins(TrainingPlugin)
 
         //.add_systems(Update, gamepad_connections)
         .init_state::<GameState>()
@@ -55,107 +86,118 @@ fn main() {
         .add_systems(OnEnter(GameState::Setup), load_assets)
         .add_systems(Update, check_characters_assets.run_if(in_state(GameState::Setup)))
 
+        //coming back from the pause menu must not set up the match again
+        .configure_sets(OnEnter(GameState::InGame), MatchSetup.run_if(not(resource_exists::<MatchFlow>)))
         .add_systems(OnEnter(GameState::InGame), (
                 spawn_camera, 
-                spawn_player
-                )
+                spawn_player_after_selection
+                    .run_if(not(resource_exists::<NetSession>))
+                    .run_if(not(resource_exists::<ReplayPlayback>)),
+                spawn_player.run_if(resource_exists::<NetSession>),
+                ).in_set(MatchSetup)
             )
 
-        .add_systems(Update, (
+        //the combat simulation advances exactly one game frame per fixed step
+        .insert_resource(Time::<Fixed>::from_hz(MAX_FRAME_RATE as f64))
+        .configure_sets(CombatStep, (
+                CombatSet::Input,
+                CombatSet::Movement,
+                CombatSet::Hits,
+                CombatSet::Animation,
+                CombatSet::Rounds,
+            ).chain()
+        )
+        .add_systems(PreUpdate, (
                 keyboard_input_system,
-                execute_animations,
-                health_ui,
-            ).run_if(in_state(GameState::InGame))
+                gamepad_input_system,
+            ).after(InputSystem)
+            .run_if(in_state(GameState::InGame))
+            //a replay plays its own inputs
+            .run_if(not(resource_exists::<ReplayPlayback>))
+        )
+        .add_systems(CombatStep, (
+                apply_player_input.in_set(CombatSet::Input),
+                (apply_velocity, land_juggled, clamp_to_stage, resolve_pushboxes, update_facing).chain().in_set(CombatSet::Movement),
+                execute_animations.in_set(CombatSet::Animation),
+            )
+        )
+        //in netplay the rollback session drives the combat step instead
+        .add_systems(FixedUpdate, run_combat_step
+            .run_if(in_state(GameState::InGame))
+            .run_if(not(resource_exists::<NetSession>))
         )
         .run();
 }
 
+//fighters start spread around the center of the stage
+const START_DISTANCE: f32 = 400.;
+
+pub fn start_position(handle: usize, players: usize) -> f32 {
+    (handle as f32 - (players.max(1) - 1) as f32 / 2.) * START_DISTANCE
+}
+
+pub fn fighter_bundle(
+    character: &Character,
+    character_id: AssetId<Character>,
+    handle: usize,
+    x: f32,
+    asset_server: &AssetServer,
+    texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
+) -> impl Bundle {
+    let texture: Handle<Image> = asset_server.load(&character.sprite_sheet);
+    let layout = TextureAtlasLayout::from_grid(Vec2::splat(SPRITE_SIZE), SPRITE_COLUMNS, SPRITE_ROWS, None, None);
+    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+
+    (PlayerAnimationManagement::new(character.animations()),
+     Speed(character.speed.clone()),
+     Health::new(character.health.clone()),
+     Velocity::default(),
+     PlayerInput::default(),
+     PlayerHandle(handle),
+     Facing::default(),
+     Name::new(character.name.clone()),
+     CharacterAsset(character_id),
+     Player,
+     character.hurtbox.clone(),
+     character.pushbox.clone(),
+     TextureAtlas {
+         layout: texture_atlas_layout,
+         index: 0
+     },
+     SpriteBundle {
+        transform: Transform::from_scale(Vec3::splat(6.0)).with_translation(Vec3::new(x, 0., 0.)),
+        texture,
+        ..default()
+    },
+    )
+}
+
+//every player that picked a character becomes its fighter,
+//keeping the device used in the selection screen
 pub fn spawn_player_after_selection(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    query: Query<&SelectedCharacter, With<Player>>
+    query: Query<(Entity, &InputController, &SelectedCharacter), With<Player>>
 ) {
     commands.insert_resource(ClearColor(Color::GRAY));
+
+    //keyboard players come first, then the lowest gamepad id
+    let mut players: Vec<_> = query.iter().collect();
+    players.sort_by_key(|(_, input_controller, _)| match input_controller {
+        InputController::Keyboard(layout) => (0, *layout as usize),
+        InputController::Gamepad(gamepad) => (1, gamepad.id),
+    });
+    let players_n = players.len();
     
-    for character in &query {
-        let character = &character.0;
-        let name = character.name.clone();
-        let hurtbox = character.hurtbox.clone();
-        let moveset = character.moveset.clone();
-        let path = &character.sprite_sheet;
-        
-        let texture: Handle<Image> = asset_server.load(path);
-        let layout = TextureAtlasLayout::from_grid(Vec2::splat(64.), 3, 3, None, None);
-        let texture_atlas_layout = texture_atlas_layouts.add(layout);
-
-        let animations: HashMap<AnimationState, (AnimationManager, Option<Attack>)> =
-                HashMap::from([
-                    (AnimationState::Idle, (AnimationManager::new(character.idle.clone()), None)),
-                    (AnimationState::Jump, (AnimationManager::new(character.jump.clone()), None)),
-                    (AnimationState::Block, (AnimationManager::new(character.block.clone()), None)),
-                    (AnimationState::Forward, (AnimationManager::new(character.forward.clone()), None)),
-                    (AnimationState::Backward, (AnimationManager::new(character.backward.clone()), None)),
-                    (AnimationState::HeavyAttack, (AnimationManager::new(moveset.heavy.animation.clone()), Some(moveset.heavy.clone()))),
-                    (AnimationState::LightAttack, (AnimationManager::new(moveset.light.animation.clone()), Some(moveset.light.clone())))
-                ]);
-        commands.spawn(
-            (PlayerAnimationManagement::new(animations),
-             Speed(character.speed.clone()),
-             Health::new(character.health.clone()),
-             Velocity::default(),
-             Name::new(name),
-             Player,
-             hurtbox,
-             TextureAtlas {
-                 layout: texture_atlas_layout.clone(),
-                 index: 0
-             },
-             SpriteBundle {
-                transform: Transform::from_scale(Vec3::splat(6.0)),
-                texture: texture.clone(),
-                ..default()
-            },
-            )
+    for (handle, (entity, _, character)) in players.into_iter().enumerate() {
+        let x = start_position(handle, players_n);
+        commands.entity(entity).insert(
+            fighter_bundle(&character.0, character.1, handle, x, &asset_server, &mut texture_atlas_layouts)
         );
     }
 }
 
-fn health_ui(
-    mut commands: Commands,
-    query: Query<(&Name, &Health), With<Player>>
-) {
-    for (name, health) in &query {
-        commands.spawn((
-            TextBundle::from_section(name, TextStyle {
-                font_size: 30.,
-                ..default()
-            })
-            .with_style(Style {
-                position_type: PositionType::Absolute,
-                top: Val::Px(0.),
-                left: Val::Px(0.),
-                ..default()
-            })
-
-        ));
-        commands.spawn((
-            TextBundle::from_section(health.value().to_string(), TextStyle {
-                font_size: 30.,
-                ..default()
-            })
-            .with_style(Style {
-                position_type: PositionType::Absolute,
-                top: Val::Px(0.),
-                right: Val::Px(0.),
-                ..default()
-            })
-        ));
-    }
-}
-
-
-
 pub fn spawn_camera(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default());
 }
@@ -168,6 +210,7 @@ pub enum GameState {
     Loading,
 
     TitleScreen,
+    Options,
     CharacterSelection,
 
     InGame,
@@ -176,6 +219,25 @@ pub enum GameState {
 }
 
 
+//runs a single game frame of the combat simulation
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CombatStep;
+
+//systems that spawn a new match when entering InGame
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatchSetup;
+
+//steps of a single game frame of the combat simulation
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CombatSet {
+    Input,
+    Movement,
+    Hits,
+    Animation,
+    Rounds,
+}
+
+
 #[derive(Resource, Default)]
 pub struct CharacterHandle(pub Handle<Character>);
 
@@ -191,11 +253,19 @@ pub fn load_assets(
 
 pub fn check_characters_assets(
     mut next_state: ResMut<NextState<GameState>>,
+    asset_server: Res<AssetServer>,
     sprite_folder: Res<CharacterFolder>,
-    mut events: EventReader<AssetEvent<LoadedFolder>>,
+    stage: Res<StageHandle>,
+    session: Option<Res<NetSession>>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
-    for event in events.read() {
-        if event.is_loaded_with_dependencies(&sprite_folder.0) {
+    if asset_server.is_loaded_with_dependencies(&sprite_folder.0)
+        && asset_server.is_loaded_with_dependencies(&stage.0) {
+        //both netplay instances skip the menus and fight with the same character,
+        //a replay already knows its characters
+        if session.is_some() || playback.is_some() {
+            next_state.set(GameState::InGame);
+        } else {
             next_state.set(GameState::TitleScreen);
         }
     }
@@ -204,7 +274,7 @@ pub fn check_characters_assets(
 pub fn create_characters(
     folder: &LoadedFolder,
     character_assets: Res<Assets<Character>>,
-) -> Vec<Character> {
+) -> Vec<(AssetId<Character>, Character)> {
     let mut characters = Vec::new();
     // Build a texture atlas using the individual sprites
     for handle in folder.handles.iter() {
@@ -217,12 +287,13 @@ pub fn create_characters(
             continue;
         };
 
-        characters.push(character.clone());
+        characters.push((id, character.clone()));
     }
 
     characters
 }
 
+//netplay skips the selection screen, both fighters use the first character
 pub fn spawn_player(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -236,44 +307,11 @@ pub fn spawn_player(
     let loaded_folder = loaded_folders.get(&characters.0).unwrap();
     let pgs = create_characters(&loaded_folder, loader);
     
-    let character = &pgs.first().unwrap();
-    let name = character.name.clone();
-    let hurtbox = character.hurtbox.clone();
-    let moveset = character.moveset.clone();
-    let path = &character.sprite_sheet;
-    
-    let texture: Handle<Image> = asset_server.load(path);
-    let layout = TextureAtlasLayout::from_grid(Vec2::splat(64.), 3, 3, None, None);
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
-
-    let animations: HashMap<AnimationState, (AnimationManager, Option<Attack>)> =
-            HashMap::from([
-                (AnimationState::Idle, (AnimationManager::new(character.idle.clone()), None)),
-                (AnimationState::Jump, (AnimationManager::new(character.jump.clone()), None)),
-                (AnimationState::Block, (AnimationManager::new(character.block.clone()), None)),
-                (AnimationState::Forward, (AnimationManager::new(character.forward.clone()), None)),
-                (AnimationState::Backward, (AnimationManager::new(character.backward.clone()), None)),
-                (AnimationState::HeavyAttack, (AnimationManager::new(moveset.heavy.animation.clone()), Some(moveset.heavy.clone()))),
-                (AnimationState::LightAttack, (AnimationManager::new(moveset.light.animation.clone()), Some(moveset.light.clone())))
-            ]);
-    commands.spawn(
-        (PlayerAnimationManagement::new(animations),
-         Speed(character.speed.clone()),
-         Health::new(character.health.clone()),
-         Velocity::default(),
-         Name::new(name),
-         Player,
-         hurtbox,
-         TextureAtlas {
-             layout: texture_atlas_layout.clone(),
-             index: 0
-         },
-         SpriteBundle {
-            transform: Transform::from_scale(Vec3::splat(6.0)),
-            texture: texture.clone(),
-            ..default()
-        },
-        )
-    );
+    let (character_id, character) = pgs.first().unwrap();
+    for handle in 0..NET_PLAYERS {
+        let x = start_position(handle, NET_PLAYERS);
+        commands.spawn(
+            fighter_bundle(character, *character_id, handle, x, &asset_server, &mut texture_atlas_layouts)
+        );
+    }
 }
-
@@ -0,0 +1,229 @@
+use bevy::prelude::*;
+
+use crate::{components::KeyboardLayout, controls::{rebind, Action, Controls}, spawn_camera, GameState};
+
+const OPTION_COLOR: Color = Color::WHITE;
+const SELECTED_COLOR: Color = Color::YELLOW;
+
+
+//rebinding screen reached from the title, saved when leaving it
+pub struct OptionsPlugin;
+
+impl Plugin for OptionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Options), (spawn_camera, options_ui))
+            .add_systems(Update, (options_input, update_options_ui)
+                .chain()
+                .run_if(in_state(GameState::Options))
+            )
+            .add_systems(OnExit(GameState::Options), (
+                    despawn_all_with::<OptionsUi>,
+                    despawn_all_with::<Camera2d>,
+                    save_controls,
+                )
+            );
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Keyboard(KeyboardLayout),
+    Gamepad,
+}
+
+impl Device {
+    fn next(&self) -> Device {
+        match self {
+            Device::Keyboard(KeyboardLayout::Full) => Device::Keyboard(KeyboardLayout::Left),
+            Device::Keyboard(KeyboardLayout::Left) => Device::Keyboard(KeyboardLayout::Right),
+            Device::Keyboard(KeyboardLayout::Right) => Device::Gamepad,
+            Device::Gamepad => Device::Keyboard(KeyboardLayout::Full),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Device::Keyboard(KeyboardLayout::Full) => "Keyboard",
+            Device::Keyboard(KeyboardLayout::Left) => "Shared keyboard, left",
+            Device::Keyboard(KeyboardLayout::Right) => "Shared keyboard, right",
+            Device::Gamepad => "Gamepad",
+        }
+    }
+}
+
+//the device row, one row per action, then the reset and back rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionsRow {
+    Device,
+    Binding(Action),
+    Reset,
+    Back,
+}
+
+impl OptionsRow {
+    fn all() -> Vec<OptionsRow> {
+        let mut rows = vec![OptionsRow::Device];
+        rows.extend(Action::ALL.map(OptionsRow::Binding));
+        rows.extend([OptionsRow::Reset, OptionsRow::Back]);
+        rows
+    }
+}
+
+#[derive(Resource)]
+struct OptionsMenu {
+    device: Device,
+    selected: usize,
+    //the selected action takes the next key or button pressed
+    waiting: bool,
+}
+
+#[derive(Component)]
+struct OptionsUi;
+
+#[derive(Component)]
+struct OptionsRowText(usize);
+
+
+fn options_ui(mut commands: Commands) {
+    commands.insert_resource(OptionsMenu { device: Device::Keyboard(KeyboardLayout::Full), selected: 0, waiting: false });
+    commands.spawn((
+        OptionsUi,
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(4.),
+                ..default()
+            },
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Controls", TextStyle {
+            font_size: 50.,
+            ..default()
+        }));
+        for i in 0..OptionsRow::all().len() {
+            parent.spawn((
+                OptionsRowText(i),
+                TextBundle::from_section("", TextStyle {
+                    font_size: 26.,
+                    color: OPTION_COLOR,
+                    ..default()
+                }),
+            ));
+        }
+    });
+}
+
+fn options_input(
+    mut controls: ResMut<Controls>,
+    mut menu: ResMut<OptionsMenu>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let rows = OptionsRow::all();
+
+    if menu.waiting {
+        let OptionsRow::Binding(action) = rows[menu.selected] else {
+            menu.waiting = false;
+            return;
+        };
+        match menu.device {
+            Device::Keyboard(layout) => if let Some(key) = keys.get_just_pressed().next() {
+                if !rebind(controls.layout_mut(layout), action, *key) {
+                    warn!("Could not bind {key:?} to {}, the swap would clash with another action", action.label());
+                }
+                menu.waiting = false;
+            },
+            Device::Gamepad => if let Some(button) = buttons.get_just_pressed().next() {
+                if !rebind(&mut controls.gamepad, action, button.button_type) {
+                    warn!("Could not bind {:?} to {}, the swap would clash with another action", button.button_type, action.label());
+                }
+                menu.waiting = false;
+            },
+        }
+        return;
+    }
+
+    let pressed = |action| controls.any_key_just_pressed(&keys, action)
+        || gamepads.iter().any(|gamepad| controls.button_just_pressed(&buttons, gamepad, action));
+
+    if pressed(Action::Up) {
+        menu.selected = (menu.selected + rows.len() - 1) % rows.len();
+    }
+    if pressed(Action::Down) {
+        menu.selected = (menu.selected + 1) % rows.len();
+    }
+    if pressed(Action::Cancel) {
+        next_state.set(GameState::TitleScreen);
+        return;
+    }
+    if !pressed(Action::Confirm) {
+        return;
+    }
+
+    match rows[menu.selected] {
+        OptionsRow::Device => {
+            menu.device = menu.device.next();
+        },
+        OptionsRow::Binding(_) => menu.waiting = true,
+        OptionsRow::Reset => {
+            let defaults = Controls::default();
+            match menu.device {
+                Device::Keyboard(layout) => *controls.layout_mut(layout) = defaults.layout(layout).clone(),
+                Device::Gamepad => controls.gamepad = defaults.gamepad,
+            }
+        },
+        OptionsRow::Back => next_state.set(GameState::TitleScreen),
+    }
+}
+
+fn update_options_ui(
+    controls: Res<Controls>,
+    menu: Res<OptionsMenu>,
+    mut query: Query<(&OptionsRowText, &mut Text)>,
+) {
+    let rows = OptionsRow::all();
+    for (row, mut text) in query.iter_mut() {
+        let selected = row.0 == menu.selected;
+        let value = match rows[row.0] {
+            OptionsRow::Device => format!("Device: {}", menu.device.label()),
+            OptionsRow::Binding(action) if selected && menu.waiting => format!("{}: press a button...", action.label()),
+            OptionsRow::Binding(action) => {
+                let bound: Vec<String> = match menu.device {
+                    Device::Keyboard(layout) => controls.keys(layout, action).iter().map(|key| format!("{key:?}")).collect(),
+                    Device::Gamepad => controls.buttons(action).iter().map(|button| format!("{button:?}")).collect(),
+                };
+                format!("{}: {}", action.label(), bound.join(" / "))
+            },
+            OptionsRow::Reset => "Reset to defaults".to_string(),
+            OptionsRow::Back => "Back".to_string(),
+        };
+
+        let section = &mut text.sections[0];
+        section.value = value;
+        section.style.color = if selected { SELECTED_COLOR } else { OPTION_COLOR };
+    }
+}
+
+fn save_controls(controls: Res<Controls>) {
+    match controls.save() {
+        Ok(path) => info!("Saved controls to {}", path.display()),
+        Err(err) => error!("Could not save controls: {err}"),
+    }
+}
+
+fn despawn_all_with<C: Component>(
+    query: Query<Entity, With<C>>,
+    mut commands: Commands,
+)
+{
+    query.iter().for_each(|x| commands.entity(x).despawn_recursive());
+}
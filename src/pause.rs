@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+
+use crate::{
+    components::{InputController, Player}, controls::{Action, Controls}, round::{end_match, RestartRound},
+    training::{Training, TRAINING_SLOTS}, GameState, NetSession, ReplayPlayback,
+};
+
+const OPTION_COLOR: Color = Color::WHITE;
+const SELECTED_COLOR: Color = Color::YELLOW;
+
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        //a netplay match cannot be stopped by only one of the players,
+        //a replay cannot change what was recorded
+        app.add_systems(Update, pause_game
+                .run_if(in_state(GameState::InGame))
+                .run_if(not(resource_exists::<NetSession>))
+                .run_if(not(resource_exists::<ReplayPlayback>))
+            )
+            .add_systems(OnEnter(GameState::Pause), pause_menu_ui)
+            .add_systems(Update, (pause_menu_input, highlight_selected_option)
+                .chain()
+                .run_if(in_state(GameState::Pause))
+            )
+            .add_systems(OnExit(GameState::Pause), (
+                    despawn_all_with::<PauseMenuUi>,
+                    remove_pause_menu,
+                )
+            )
+            .add_systems(OnTransition { from: GameState::Pause, to: GameState::CharacterSelection }, end_match)
+            .add_systems(OnTransition { from: GameState::Pause, to: GameState::TitleScreen }, end_match);
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseOption {
+    Resume,
+    //back and forward change the training settings
+    DummyBehavior,
+    RecordingSlot,
+    RestartRound,
+    CharacterSelect,
+    QuitToTitle,
+}
+
+impl PauseOption {
+    fn all(training: bool) -> Vec<PauseOption> {
+        let mut options = vec![PauseOption::Resume];
+        if training {
+            options.extend([PauseOption::DummyBehavior, PauseOption::RecordingSlot]);
+        }
+        options.extend([PauseOption::RestartRound, PauseOption::CharacterSelect, PauseOption::QuitToTitle]);
+        options
+    }
+
+    fn label(&self, training: Option<&Training>) -> String {
+        match self {
+            PauseOption::Resume => "Resume".to_string(),
+            PauseOption::DummyBehavior => {
+                let behavior = training.map_or("", |training| training.behavior.label());
+                format!("Dummy: < {behavior} >")
+            },
+            PauseOption::RecordingSlot => {
+                let slot = training.map_or(0, |training| training.slot);
+                let seconds = training.map_or(0., |training| training.slot_seconds(slot));
+                format!("Slot: < {}/{TRAINING_SLOTS} > ({seconds:.1}s)", slot + 1)
+            },
+            PauseOption::RestartRound => "Restart round".to_string(),
+            PauseOption::CharacterSelect => "Character select".to_string(),
+            PauseOption::QuitToTitle => "Quit to title".to_string(),
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct PauseMenu {
+    //the device that opened the menu, nobody else can use it
+    pub paused_by: InputController,
+    options: Vec<PauseOption>,
+    selected: usize,
+}
+
+#[derive(Component)]
+struct PauseMenuUi;
+
+#[derive(Component)]
+struct PauseMenuOption(usize);
+
+
+//Start stops the combat, which only runs while InGame,
+//on the device of any of the fighters
+fn pause_game(
+    mut commands: Commands,
+    controls: Res<Controls>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    fighters: Query<&InputController, With<Player>>,
+    training: Option<Res<Training>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(paused_by) = fighters.iter().find(|controller| {
+        controls.just_pressed(**controller, &keys, &buttons, Action::Start)
+    }) else {
+        return;
+    };
+
+    commands.insert_resource(PauseMenu {
+        paused_by: *paused_by,
+        options: PauseOption::all(training.is_some()),
+        selected: 0,
+    });
+    next_state.set(GameState::Pause);
+}
+
+fn pause_menu_ui(mut commands: Commands, menu: Res<PauseMenu>, training: Option<Res<Training>>) {
+    commands.spawn((
+        PauseMenuUi,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::rgba(0., 0., 0., 0.6)),
+            z_index: ZIndex::Global(10),
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Pause", TextStyle {
+            font_size: 60.,
+            ..default()
+        }));
+        for (i, option) in menu.options.iter().enumerate() {
+            parent.spawn((
+                PauseMenuOption(i),
+                TextBundle::from_section(option.label(training.as_deref()), TextStyle {
+                    font_size: 40.,
+                    color: OPTION_COLOR,
+                    ..default()
+                }),
+            ));
+        }
+    });
+}
+
+fn pause_menu_input(
+    mut commands: Commands,
+    controls: Res<Controls>,
+    mut menu: ResMut<PauseMenu>,
+    mut training: Option<ResMut<Training>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut restart: EventWriter<RestartRound>,
+) {
+    let pressed = |action| controls.just_pressed(menu.paused_by, &keys, &buttons, action);
+    let (up, down, confirm, back) = (
+        pressed(Action::Up),
+        pressed(Action::Down),
+        pressed(Action::Confirm),
+        pressed(Action::Start) || pressed(Action::Cancel),
+    );
+    let (forward, backward) = (pressed(Action::Forward), pressed(Action::Back));
+
+    let options = menu.options.len();
+    if up {
+        menu.selected = (menu.selected + options - 1) % options;
+    }
+    if down {
+        menu.selected = (menu.selected + 1) % options;
+    }
+
+    if let Some(training) = training.as_mut() {
+        match menu.options[menu.selected] {
+            PauseOption::DummyBehavior if forward => training.behavior = training.behavior.next(),
+            PauseOption::DummyBehavior if backward => training.behavior = training.behavior.prev(),
+            PauseOption::RecordingSlot if forward => training.next_slot(),
+            PauseOption::RecordingSlot if backward => training.prev_slot(),
+            _ => {},
+        }
+    }
+
+    if back {
+        next_state.set(GameState::InGame);
+        return;
+    }
+    if !confirm {
+        return;
+    }
+
+    match menu.options[menu.selected] {
+        PauseOption::Resume => next_state.set(GameState::InGame),
+        PauseOption::DummyBehavior | PauseOption::RecordingSlot => {},
+        PauseOption::RestartRound => {
+            restart.send(RestartRound);
+            next_state.set(GameState::InGame);
+        },
+        PauseOption::CharacterSelect => next_state.set(GameState::CharacterSelection),
+        PauseOption::QuitToTitle => {
+            commands.insert_resource(ClearColor(Color::BLACK));
+            next_state.set(GameState::TitleScreen);
+        },
+    }
+}
+
+fn highlight_selected_option(
+    menu: Res<PauseMenu>,
+    training: Option<Res<Training>>,
+    mut query: Query<(&PauseMenuOption, &mut Text)>,
+) {
+    for (option, mut text) in query.iter_mut() {
+        let color = if option.0 == menu.selected { SELECTED_COLOR } else { OPTION_COLOR };
+        let section = &mut text.sections[0];
+        section.value = menu.options[option.0].label(training.as_deref());
+        section.style.color = color;
+    }
+}
+
+fn remove_pause_menu(mut commands: Commands) {
+    commands.remove_resource::<PauseMenu>();
+}
+
+fn despawn_all_with<C: Component>(
+    query: Query<Entity, With<C>>,
+    mut commands: Commands,
+)
+{
+    query.iter().for_each(|x| commands.entity(x).despawn_recursive());
+}
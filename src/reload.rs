@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+
+use crate::{components::*, GameState, NetSession};
+
+
+//rebuilds the fighters when their character file changes on disk,
+//files are only watched when built with the `hot-reload` feature
+pub struct HotReloadPlugin;
+
+impl Plugin for HotReloadPlugin {
+    fn build(&self, app: &mut App) {
+        //both netplay instances would need the same change on the same frame
+        app.add_systems(Update, reload_characters
+            .run_if(in_state(GameState::InGame).or_else(in_state(GameState::Pause)))
+            .run_if(not(resource_exists::<NetSession>))
+        );
+    }
+}
+
+
+//the match goes on: health, position and the running animations are kept
+fn reload_characters(
+    mut events: EventReader<AssetEvent<Character>>,
+    characters: Res<Assets<Character>>,
+    mut fighters: Query<(
+        &CharacterAsset, &mut PlayerAnimationManagement, &mut Speed, &mut Hurtbox, &mut Pushbox
+    ), With<Player>>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        let Some(character) = characters.get(*id) else {
+            continue;
+        };
+
+        for (asset, mut animation, mut speed, mut hurtbox, mut pushbox) in fighters.iter_mut() {
+            if asset.0 != *id {
+                continue;
+            }
+            animation.reload(character.animations());
+            speed.0 = character.speed;
+            *hurtbox = character.hurtbox.clone();
+            *pushbox = character.pushbox.clone();
+        }
+        info!("Reloaded character {}", character.name);
+    }
+}
@@ -0,0 +1,348 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+
+use crate::{
+    components::*, fighter_bundle, start_position, spawn_player_after_selection,
+    round::{MatchRules, RestartRound},
+    training::Training,
+    CombatSet, CombatStep, GameState, MatchSetup, NetSession,
+};
+
+//bumped whenever the layout of the file or the meaning of the inputs changes
+pub const REPLAY_VERSION: u32 = 1;
+const REPLAY_FOLDER: &str = "replays";
+
+
+//records every local match and plays one back with `--replay <file>`,
+//it has to be added after the RoundPlugin to replace its rules
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = replay_arg(std::env::args().skip(1)) {
+            match Replay::load(&path) {
+                Ok(replay) => {
+                    app.insert_resource(MatchRules {
+                            rounds: replay.rounds,
+                            round_frames: replay.round_frames,
+                        })
+                        .insert_resource(ReplayPlayback { replay, frame: 0 });
+                },
+                Err(err) => error!("Could not load replay {}: {err}", path.display()),
+            }
+        }
+
+        app.add_systems(OnEnter(GameState::InGame), (
+                    //training has no rounds to replay
+                    start_recording
+                        .after(spawn_player_after_selection)
+                        .run_if(not(resource_exists::<NetSession>))
+                        .run_if(not(resource_exists::<ReplayPlayback>))
+                        .run_if(not(resource_exists::<Training>)),
+                    spawn_replay_fighters.run_if(resource_exists::<ReplayPlayback>),
+                ).in_set(MatchSetup)
+            )
+            //inputs are stored for every combat step, even when the round is over
+            .add_systems(CombatStep, (
+                    record_inputs.run_if(resource_exists::<ReplayRecorder>),
+                    play_inputs.run_if(resource_exists::<ReplayPlayback>),
+                ).before(CombatSet::Input)
+            )
+            .add_systems(OnEnter(GameState::GameEnded), save_replay.run_if(resource_exists::<ReplayRecorder>))
+            .add_systems(OnExit(GameState::GameEnded), stop_replay);
+    }
+}
+
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Replay {
+    pub version: u32,
+    pub rounds: u8,
+    pub round_frames: u32,
+    //indexed by player handle
+    pub players: Vec<ReplayPlayer>,
+    //combat steps at which a round was restarted from the pause menu
+    pub restarts: Vec<u32>,
+    //one entry per combat step, with the input of every player handle
+    pub inputs: Vec<Vec<u16>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ReplayPlayer {
+    //asset path of the character file
+    pub character: String,
+    //content hash of the character file when the match was played
+    pub hash: u64,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let replay: Replay = ron::de::from_str(&text).map_err(|err| err.to_string())?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!("version {} is not supported, expected {REPLAY_VERSION}", replay.version));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::ser::to_string(self).map_err(|err| err.to_string())?;
+        fs::write(path, text).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    //taken when the match starts, a character edited during the match gets the warning on playback
+    players: Vec<ReplayPlayer>,
+    restarts: Vec<u32>,
+    inputs: Vec<Vec<u16>>,
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    //next combat step to play
+    frame: u32,
+}
+
+
+//smash --replay <file>
+fn replay_arg(args: impl Iterator<Item = String>) -> Option<PathBuf> {
+    let args: Vec<String> = args.collect();
+    let position = args.iter().position(|arg| arg == "--replay")?;
+    let Some(path) = args.get(position + 1) else {
+        error!("--replay expects a replay file");
+        return None;
+    };
+    Some(PathBuf::from(path))
+}
+
+//FNV-1a, stable across builds and platforms
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn character_hash(asset_path: &str) -> Option<u64> {
+    let bytes = fs::read(FileAssetReader::get_base_path().join("assets").join(asset_path)).ok()?;
+    Some(content_hash(&bytes))
+}
+
+fn start_recording(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    fighters: Query<(&PlayerHandle, &CharacterAsset)>,
+) {
+    let mut fighters: Vec<_> = fighters.iter().collect();
+    fighters.sort_by_key(|(handle, _)| handle.0);
+
+    let mut players = Vec::new();
+    for (_, asset) in fighters {
+        let Some(path) = asset_server.get_path(asset.0) else {
+            warn!("Match not recorded, a character has no file");
+            return;
+        };
+        let character = path.path().to_string_lossy().to_string();
+        let hash = character_hash(&character).unwrap_or(0);
+        players.push(ReplayPlayer { character, hash });
+    }
+    commands.insert_resource(ReplayRecorder { players, ..default() });
+}
+
+fn stop_replay(mut commands: Commands) {
+    commands.remove_resource::<ReplayRecorder>();
+    commands.remove_resource::<ReplayPlayback>();
+}
+
+pub fn record_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    mut restarts: EventReader<RestartRound>,
+    fighters: Query<(&PlayerHandle, &PlayerInput)>,
+) {
+    if restarts.read().count() > 0 {
+        let frame = recorder.inputs.len() as u32;
+        recorder.restarts.push(frame);
+    }
+
+    let players = fighters.iter().map(|(handle, _)| handle.0 + 1).max().unwrap_or(0);
+    let mut inputs = vec![0; players];
+    for (handle, input) in fighters.iter() {
+        inputs[handle.0] = input.current.0;
+    }
+    recorder.inputs.push(inputs);
+}
+
+//replaces the devices: the recorded input goes through the same combat systems
+pub fn play_inputs(
+    mut restart: EventWriter<RestartRound>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut fighters: Query<(&PlayerHandle, &mut PlayerInput)>,
+) {
+    let frame = playback.frame;
+    if playback.replay.restarts.contains(&frame) {
+        restart.send(RestartRound);
+    }
+
+    let inputs = playback.replay.inputs.get(frame as usize);
+    for (handle, mut input) in fighters.iter_mut() {
+        let recorded = inputs.and_then(|inputs| inputs.get(handle.0)).copied().unwrap_or(0);
+        input.current = InputFrame(recorded);
+    }
+    playback.frame += 1;
+}
+
+fn save_replay(
+    rules: Res<MatchRules>,
+    recorder: Res<ReplayRecorder>,
+) {
+    let replay = Replay {
+        version: REPLAY_VERSION,
+        rounds: rules.rounds,
+        round_frames: rules.round_frames,
+        players: recorder.players.clone(),
+        restarts: recorder.restarts.clone(),
+        inputs: recorder.inputs.clone(),
+    };
+
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let path = PathBuf::from(REPLAY_FOLDER).join(format!("{seconds}.replay.ron"));
+    let saved = fs::create_dir_all(REPLAY_FOLDER)
+        .map_err(|err| err.to_string())
+        .and_then(|_| replay.save(&path));
+    match saved {
+        Ok(()) => info!("Saved replay {}", path.display()),
+        Err(err) => error!("Could not save replay {}: {err}", path.display()),
+    }
+}
+
+//fighters are built from the characters of the replay instead of the selection
+fn spawn_replay_fighters(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    characters: Res<Assets<Character>>,
+    playback: Res<ReplayPlayback>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    commands.insert_resource(ClearColor(Color::GRAY));
+
+    let players = playback.replay.players.len();
+    for (handle, player) in playback.replay.players.iter().enumerate() {
+        let Some(id) = asset_server.get_path_id(player.character.clone()) else {
+            error!("Replay character {} is not loaded", player.character);
+            continue;
+        };
+        let id = id.typed_unchecked::<Character>();
+        let Some(character) = characters.get(id) else {
+            error!("Replay character {} is not loaded", player.character);
+            continue;
+        };
+        if character_hash(&player.character) != Some(player.hash) {
+            warn!("{} changed since the replay was recorded, the playback can differ", player.character);
+        }
+
+        let x = start_position(handle, players);
+        commands.spawn(
+            fighter_bundle(character, id, handle, x, &asset_server, &mut texture_atlas_layouts)
+        );
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            rounds: 3,
+            round_frames: 5400,
+            players: vec![
+                ReplayPlayer { character: "characters/pg.ron".to_string(), hash: 1 },
+                ReplayPlayer { character: "characters/test.ron".to_string(), hash: u64::MAX },
+            ],
+            restarts: vec![2],
+            inputs: vec![vec![0, 0], vec![InputButton::Light as u16, 0], vec![0, u16::MAX]],
+        }
+    }
+
+    //a file of its own in the temporary folder, removed at the end of the test
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("smash-{}-{name}", std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let file = TempFile::new("round-trip.replay.ron");
+        let replay = replay();
+        replay.save(&file.0).unwrap();
+        let loaded = Replay::load(&file.0).unwrap();
+
+        assert_eq!(loaded.rounds, replay.rounds);
+        assert_eq!(loaded.round_frames, replay.round_frames);
+        assert_eq!(loaded.restarts, replay.restarts);
+        assert_eq!(loaded.inputs, replay.inputs);
+        assert_eq!(loaded.players.len(), 2);
+        assert_eq!(loaded.players[1].character, "characters/test.ron");
+        assert_eq!(loaded.players[1].hash, u64::MAX);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let file = TempFile::new("version.replay.ron");
+        let replay = Replay { version: REPLAY_VERSION + 1, ..replay() };
+        replay.save(&file.0).unwrap();
+
+        let err = Replay::load(&file.0).unwrap_err();
+        assert!(err.contains(&format!("version {}", REPLAY_VERSION + 1)), "{err}");
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let file = TempFile::new("broken.replay.ron");
+        fs::write(&file.0, "(version: 1, rounds:").unwrap();
+        assert!(Replay::load(&file.0).is_err());
+        assert!(Replay::load(&TempFile::new("missing.replay.ron").0).is_err());
+    }
+
+    #[test]
+    fn replay_arg_takes_the_next_argument() {
+        assert_eq!(replay_arg(args("--replay replays/1.replay.ron")), Some(PathBuf::from("replays/1.replay.ron")));
+        assert_eq!(replay_arg(args("--delay 2 --replay a.ron --player 1")), Some(PathBuf::from("a.ron")));
+        assert_eq!(replay_arg(args("--netplay 7000 127.0.0.1:7001")), None);
+        assert_eq!(replay_arg(args("")), None);
+        assert_eq!(replay_arg(args("--replay")), None);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        //reference values of 64 bit FNV-1a
+        assert_eq!(content_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(content_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(content_hash(b"foobar"), 0x85944171f73967e8);
+        assert_ne!(content_hash(b"damage: 80"), content_hash(b"damage: 81"));
+    }
+}
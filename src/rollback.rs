@@ -0,0 +1,775 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::{components::*, round::MatchFlow, CombatStep, GameState};
+
+pub const NET_PLAYERS: usize = 2;
+
+//how many frames the simulation may run ahead of the last confirmed remote input
+const MAX_PREDICTION_FRAMES: u32 = 8;
+//unacknowledged local inputs are resent in every packet, up to this amount
+const MAX_INPUTS_PER_PACKET: usize = 32;
+const NO_ACK: u32 = u32::MAX;
+
+
+pub struct RollbackPlugin;
+
+impl Plugin for RollbackPlugin {
+    fn build(&self, app: &mut App) {
+        let config = match NetConfig::from_args(std::env::args().skip(1)) {
+            Ok(Some(config)) => config,
+            Ok(None) => return,
+            Err(err) => {
+                error!("Could not start netplay: {err}");
+                return;
+            },
+        };
+
+        match NetSession::new(config) {
+            Ok(session) => {
+                app.insert_resource(session)
+                    .add_systems(FixedUpdate, rollback_step.run_if(in_state(GameState::InGame)));
+            },
+            Err(err) => error!("Could not open the netplay socket: {err}"),
+        }
+    }
+}
+
+
+//smash --netplay <local port> <remote address> --player <0|1> [--delay frames] [--latency ms] [--loss 0..1]
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    pub local_port: u16,
+    pub remote: SocketAddr,
+    pub local_handle: usize,
+    pub input_delay: u32,
+    //artificial one-way latency added to every outgoing packet
+    pub latency: Duration,
+    //probability of dropping an outgoing packet
+    pub packet_loss: f32,
+}
+
+impl NetConfig {
+    //None when there is no --netplay argument
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let args: Vec<String> = args.collect();
+        let Some(position) = args.iter().position(|arg| arg == "--netplay") else {
+            return Ok(None);
+        };
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+
+        let local_port = args.get(position + 1)
+            .and_then(|port| port.parse().ok())
+            .ok_or("--netplay expects a local port")?;
+        let remote = args.get(position + 2)
+            .and_then(|addr| addr.parse().ok())
+            .ok_or("--netplay expects a remote address like 127.0.0.1:7001")?;
+        let local_handle = parse_or(value("--player"), 0, "--player expects 0 or 1")?;
+        if local_handle >= NET_PLAYERS {
+            return Err("--player expects 0 or 1".to_string());
+        }
+        let packet_loss = parse_or(value("--loss"), 0., "--loss expects a value between 0 and 1")?;
+        if !(0. ..=1.).contains(&packet_loss) {
+            return Err("--loss expects a value between 0 and 1".to_string());
+        }
+
+        Ok(Some(Self {
+            local_port,
+            remote,
+            local_handle,
+            input_delay: parse_or(value("--delay"), 2, "--delay expects frames")?,
+            latency: Duration::from_millis(parse_or(value("--latency"), 0, "--latency expects milliseconds")?),
+            packet_loss,
+        }))
+    }
+}
+
+//an optional argument, `default` when it is missing
+fn parse_or<T: std::str::FromStr>(value: Option<&String>, default: T, error: &str) -> Result<T, String> {
+    match value {
+        Some(value) => value.parse().map_err(|_| error.to_string()),
+        None => Ok(default),
+    }
+}
+
+
+//UDP socket that can simulate a bad connection on the sending side
+pub struct UdpTransport {
+    socket: UdpSocket,
+    remote: SocketAddr,
+    latency: Duration,
+    packet_loss: f32,
+    rng: u64,
+    //packets held back until their artificial latency elapsed
+    outgoing: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl UdpTransport {
+    pub fn bind(port: u16, remote: SocketAddr, latency: Duration, packet_loss: f32) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Ok(Self {
+            socket,
+            remote,
+            latency,
+            packet_loss,
+            rng: seed | 1,
+            outgoing: VecDeque::new(),
+        })
+    }
+
+    pub fn send(&mut self, packet: Vec<u8>) {
+        if self.random() < self.packet_loss {
+            return;
+        }
+        self.outgoing.push_back((Instant::now() + self.latency, packet));
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        let now = Instant::now();
+        while self.outgoing.front().is_some_and(|(due, _)| *due <= now) {
+            let (_, packet) = self.outgoing.pop_front().unwrap();
+            if let Err(err) = self.socket.send_to(&packet, self.remote) {
+                warn!("Failed to send packet: {err}");
+            }
+        }
+    }
+
+    pub fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        let mut buffer = [0u8; 512];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) if from == self.remote => packets.push(buffer[..len].to_vec()),
+                Ok(_) => { },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                //windows reports ICMP port unreachable as an error on the next read
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => continue,
+                Err(err) => {
+                    warn!("Failed to receive packet: {err}");
+                    break;
+                }
+            }
+        }
+        packets
+    }
+
+    //xorshift, only used to drop packets
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+
+//inputs of the sender starting from `start_frame`,
+//plus the first frame the sender is still missing from us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPacket {
+    pub ack: Option<u32>,
+    pub start_frame: u32,
+    pub inputs: Vec<InputFrame>,
+}
+
+impl InputPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(9 + self.inputs.len() * 2);
+        bytes.extend_from_slice(&self.ack.unwrap_or(NO_ACK).to_le_bytes());
+        bytes.extend_from_slice(&self.start_frame.to_le_bytes());
+        bytes.push(self.inputs.len() as u8);
+        for input in &self.inputs {
+            bytes.extend_from_slice(&input.0.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let ack = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+        let start_frame = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+        let count = *bytes.get(8)? as usize;
+        let inputs = bytes.get(9..9 + count * 2)?
+            .chunks_exact(2)
+            .map(|chunk| InputFrame(u16::from_le_bytes([chunk[0], chunk[1]])))
+            .collect();
+
+        Some(Self {
+            ack: (ack != NO_ACK).then_some(ack),
+            start_frame,
+            inputs,
+        })
+    }
+}
+
+
+//state of a fighter that has to be restored when rolling back
+#[derive(Clone)]
+pub struct FighterState {
+    pub transform: Transform,
+    pub velocity: Velocity,
+    pub health: Health,
+    pub animation: PlayerAnimationManagement,
+    pub hitstun: Option<HitStun>,
+    pub combo: Option<Combo>,
+    pub hitstop: Option<HitStop>,
+    pub juggle: Option<Juggle>,
+    pub blockstun: Option<BlockStun>,
+    pub hitboxes: Option<Hitboxes>,
+    pub attack_hits: Option<AttackHits>,
+    pub input: PlayerInput,
+    pub facing: Facing,
+    pub blocking: bool,
+}
+
+#[derive(Clone)]
+pub struct CombatSnapshot {
+    pub frame: u32,
+    pub fighters: Vec<(Entity, FighterState)>,
+    pub match_flow: Option<MatchFlow>,
+}
+
+pub fn save_combat_state(world: &mut World, frame: u32) -> CombatSnapshot {
+    let mut query = world.query_filtered::<(
+        Entity, &Transform, &Velocity, &Health, &PlayerAnimationManagement,
+        Option<&HitStun>, Option<&Combo>, Option<&HitStop>, Option<&Juggle>, Option<&BlockStun>, Option<&Hitboxes>, Option<&AttackHits>, &PlayerInput, &Facing, Has<IsBlocking>
+    ), With<Player>>();
+
+    let fighters = query.iter(world)
+        .map(|(entity, transform, velocity, health, animation, hitstun, combo, hitstop, juggle, blockstun, hitboxes, attack_hits, input, facing, blocking)| {
+            (entity, FighterState {
+                transform: *transform,
+                velocity: velocity.clone(),
+                health: health.clone(),
+                animation: animation.clone(),
+                hitstun: hitstun.cloned(),
+                combo: combo.cloned(),
+                hitstop: hitstop.cloned(),
+                juggle: juggle.cloned(),
+                blockstun: blockstun.cloned(),
+                hitboxes: hitboxes.cloned(),
+                attack_hits: attack_hits.cloned(),
+                input: *input,
+                facing: *facing,
+                blocking,
+            })
+        })
+        .collect();
+
+    let match_flow = world.get_resource::<MatchFlow>().cloned();
+
+    CombatSnapshot { frame, fighters, match_flow }
+}
+
+pub fn load_combat_state(world: &mut World, snapshot: &CombatSnapshot) {
+    if let Some(match_flow) = snapshot.match_flow.clone() {
+        world.insert_resource(match_flow);
+    }
+
+    for (entity, state) in snapshot.fighters.iter().cloned() {
+        let Some(mut fighter) = world.get_entity_mut(entity) else {
+            continue;
+        };
+        fighter.insert((
+            state.transform,
+            state.velocity,
+            state.health,
+            state.animation,
+            state.input,
+            state.facing,
+        ));
+
+        match state.hitstun {
+            Some(hitstun) => { fighter.insert(hitstun); },
+            None => { fighter.remove::<HitStun>(); },
+        }
+        match state.combo {
+            Some(combo) => { fighter.insert(combo); },
+            None => { fighter.remove::<Combo>(); },
+        }
+        match state.hitstop {
+            Some(hitstop) => { fighter.insert(hitstop); },
+            None => { fighter.remove::<HitStop>(); },
+        }
+        match state.juggle {
+            Some(juggle) => { fighter.insert(juggle); },
+            None => { fighter.remove::<Juggle>(); },
+        }
+        match state.blockstun {
+            Some(blockstun) => { fighter.insert(blockstun); },
+            None => { fighter.remove::<BlockStun>(); },
+        }
+        match state.hitboxes {
+            Some(hitboxes) => { fighter.insert(hitboxes); },
+            None => { fighter.remove::<Hitboxes>(); },
+        }
+        match state.attack_hits {
+            Some(attack_hits) => { fighter.insert(attack_hits); },
+            None => { fighter.remove::<AttackHits>(); },
+        }
+        if state.blocking {
+            fighter.insert(IsBlocking);
+        } else {
+            fighter.remove::<IsBlocking>();
+        }
+    }
+}
+
+
+#[derive(Resource)]
+pub struct NetSession {
+    pub local_handle: usize,
+    input_delay: u32,
+    transport: UdpTransport,
+    //next frame to simulate
+    frame: u32,
+    local_inputs: BTreeMap<u32, InputFrame>,
+    remote_inputs: BTreeMap<u32, InputFrame>,
+    //every remote input before this frame has been received
+    remote_confirmed: u32,
+    //every local input before this frame has been received by the remote
+    local_acked: u32,
+    //remote inputs guessed by the simulation and not confirmed yet
+    predictions: BTreeMap<u32, InputFrame>,
+    snapshots: VecDeque<CombatSnapshot>,
+    rollback_to: Option<u32>,
+}
+
+impl NetSession {
+    pub fn new(config: NetConfig) -> std::io::Result<Self> {
+        let transport = UdpTransport::bind(config.local_port, config.remote, config.latency, config.packet_loss)?;
+        //nobody can press anything during the input delay of the first frames
+        let empty: BTreeMap<u32, InputFrame> = (0..config.input_delay)
+            .map(|frame| (frame, InputFrame::default()))
+            .collect();
+
+        Ok(Self {
+            local_handle: config.local_handle,
+            input_delay: config.input_delay,
+            transport,
+            frame: 0,
+            local_inputs: empty.clone(),
+            remote_inputs: empty,
+            remote_confirmed: config.input_delay,
+            local_acked: 0,
+            predictions: BTreeMap::new(),
+            snapshots: VecDeque::new(),
+            rollback_to: None,
+        })
+    }
+
+    fn receive(&mut self) {
+        for bytes in self.transport.receive() {
+            match InputPacket::decode(&bytes) {
+                Some(packet) => self.add_packet(packet),
+                None => warn!("Discarded malformed input packet"),
+            }
+        }
+    }
+
+    fn add_packet(&mut self, packet: InputPacket) {
+        if let Some(ack) = packet.ack {
+            self.local_acked = self.local_acked.max(ack);
+        }
+        for (frame, input) in (packet.start_frame..).zip(packet.inputs) {
+            if frame < self.remote_confirmed || self.remote_inputs.contains_key(&frame) {
+                continue;
+            }
+            self.remote_inputs.insert(frame, input);
+            //the simulation already ran this frame with a wrong guess
+            if self.predictions.remove(&frame).is_some_and(|predicted| predicted != input) {
+                self.rollback_to = Some(self.rollback_to.map_or(frame, |to| to.min(frame)));
+            }
+        }
+
+        while self.remote_inputs.contains_key(&self.remote_confirmed) {
+            self.remote_confirmed += 1;
+        }
+    }
+
+    fn send(&mut self) {
+        let inputs: Vec<InputFrame> = self.local_inputs
+            .range(self.local_acked..)
+            .take(MAX_INPUTS_PER_PACKET)
+            .map(|(_, input)| *input)
+            .collect();
+        let packet = InputPacket {
+            ack: Some(self.remote_confirmed),
+            start_frame: self.local_acked,
+            inputs,
+        };
+        self.transport.send(packet.encode());
+    }
+
+    fn remote_input(&mut self, frame: u32) -> InputFrame {
+        if let Some(input) = self.remote_inputs.get(&frame) {
+            return *input;
+        }
+        //predict that the remote player keeps holding the last known buttons
+        let predicted = self.remote_inputs
+            .range(..frame)
+            .next_back()
+            .map(|(_, input)| *input)
+            .unwrap_or_default();
+        self.predictions.insert(frame, predicted);
+        predicted
+    }
+
+    fn inputs(&mut self, frame: u32) -> [InputFrame; NET_PLAYERS] {
+        let local = self.local_inputs.get(&frame).cloned().unwrap_or_default();
+        let remote = self.remote_input(frame);
+        let mut inputs = [remote; NET_PLAYERS];
+        inputs[self.local_handle] = local;
+        inputs
+    }
+
+    fn is_too_far_ahead(&self) -> bool {
+        self.frame >= self.remote_confirmed + MAX_PREDICTION_FRAMES
+    }
+
+    //drops everything that can no longer be rolled back to
+    fn discard_confirmed(&mut self) {
+        let oldest = self.remote_confirmed.min(self.frame);
+        while self.snapshots.front().is_some_and(|snapshot| snapshot.frame < oldest) {
+            self.snapshots.pop_front();
+        }
+        let oldest = self.snapshots.front().map_or(oldest, |snapshot| snapshot.frame);
+        self.remote_inputs.retain(|frame, _| *frame + 1 >= oldest);
+        self.local_inputs.retain(|frame, _| *frame >= self.local_acked.min(oldest));
+    }
+
+    fn save(&mut self, world: &mut World, frame: u32) {
+        self.snapshots.retain(|snapshot| snapshot.frame < frame);
+        self.snapshots.push_back(save_combat_state(world, frame));
+    }
+
+    fn load(&mut self, world: &mut World, frame: u32) -> bool {
+        let Some(snapshot) = self.snapshots.iter().find(|snapshot| snapshot.frame == frame) else {
+            return false;
+        };
+        load_combat_state(world, snapshot);
+        true
+    }
+
+    //saves the state at the start of `frame` and simulates it
+    fn simulate(&mut self, world: &mut World, frame: u32) {
+        self.save(world, frame);
+
+        let inputs = self.inputs(frame);
+        let mut query = world.query::<(&PlayerHandle, &mut PlayerInput)>();
+        for (handle, mut input) in query.iter_mut(world) {
+            if let Some(frame_input) = inputs.get(handle.0) {
+                input.current = *frame_input;
+            }
+        }
+
+        world.run_schedule(CombatStep);
+    }
+}
+
+
+fn local_fighter(world: &mut World, local_handle: usize) -> Option<Entity> {
+    let mut query = world.query::<(Entity, &PlayerHandle)>();
+    query.iter(world)
+        .find(|(_, handle)| handle.0 == local_handle)
+        .map(|(entity, _)| entity)
+}
+
+//replaces `run_combat_step` when playing online
+pub fn rollback_step(world: &mut World) {
+    world.resource_scope(|world, mut session: Mut<NetSession>| {
+        session.receive();
+
+        //the live input of the device, read before a rollback replays older inputs over it
+        let local = local_fighter(world, session.local_handle);
+        let sample = local
+            .and_then(|local| world.get::<PlayerInput>(local))
+            .map(|input| input.current)
+            .unwrap_or_default();
+
+        if let Some(frame) = session.rollback_to.take() {
+            if session.load(world, frame) {
+                for resimulated in frame..session.frame {
+                    session.simulate(world, resimulated);
+                }
+            } else {
+                error!("Missing snapshot for frame {frame}, the match is out of sync");
+            }
+        }
+
+        let Some(local) = local else {
+            return;
+        };
+
+        if session.is_too_far_ahead() {
+            //wait for the remote player, but keep acknowledging its inputs
+            session.send();
+            session.transport.flush();
+        } else {
+            //the sampled input is delayed to give it time to reach the remote player
+            let delayed_frame = session.frame + session.input_delay;
+            session.local_inputs.insert(delayed_frame, sample);
+            session.send();
+
+            let frame = session.frame;
+            session.simulate(world, frame);
+            session.frame += 1;
+            session.discard_confirmed();
+        }
+
+        //the simulation overwrote the input with the delayed one,
+        //keep the live sample for the next fixed step of this render frame
+        if let Some(mut input) = world.get_mut::<PlayerInput>(local) {
+            input.current = sample;
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::round::MatchRules;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn input_packet_round_trip() {
+        let packet = InputPacket {
+            ack: Some(12),
+            start_frame: 40,
+            inputs: vec![InputFrame(0), InputFrame(InputButton::Light as u16 | InputButton::Right as u16), InputFrame(u16::MAX)],
+        };
+        assert_eq!(InputPacket::decode(&packet.encode()), Some(packet));
+
+        let empty = InputPacket { ack: None, start_frame: 0, inputs: Vec::new() };
+        assert_eq!(InputPacket::decode(&empty.encode()), Some(empty));
+    }
+
+    #[test]
+    fn truncated_input_packet_is_rejected() {
+        let packet = InputPacket { ack: Some(1), start_frame: 2, inputs: vec![InputFrame(3), InputFrame(4)] };
+        let bytes = packet.encode();
+        assert_eq!(InputPacket::decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(InputPacket::decode(&bytes[..5]), None);
+    }
+
+    #[test]
+    fn net_config_from_args() {
+        assert!(matches!(NetConfig::from_args(args("--replay a.ron")), Ok(None)));
+
+        let config = NetConfig::from_args(args("--netplay 7000 127.0.0.1:7001 --player 1 --delay 3 --loss 0.5"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.local_port, 7000);
+        assert_eq!(config.local_handle, 1);
+        assert_eq!(config.input_delay, 3);
+        assert_eq!(config.packet_loss, 0.5);
+
+        assert!(NetConfig::from_args(args("--netplay")).is_err());
+        assert!(NetConfig::from_args(args("--netplay 7000 nowhere")).is_err());
+        assert!(NetConfig::from_args(args("--netplay 7000 127.0.0.1:7001 --player 2")).is_err());
+        assert!(NetConfig::from_args(args("--netplay 7000 127.0.0.1:7001 --delay soon")).is_err());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut world = World::new();
+        let fighter = world.spawn((
+            Player,
+            Transform::from_xyz(-100., 0., 0.),
+            Velocity { x: 2., y: 0. },
+            Health::new(100),
+            PlayerAnimationManagement::default(),
+            PlayerInput::default(),
+            Facing::Right,
+            HitStun::new(5),
+            IsBlocking,
+        )).id();
+        world.insert_resource(MatchFlow::new(&MatchRules { rounds: 3, round_frames: 600 }));
+
+        let snapshot = save_combat_state(&mut world, 7);
+        assert_eq!(snapshot.frame, 7);
+
+        //everything the next frames could change
+        world.get_mut::<Transform>(fighter).unwrap().translation.x = 50.;
+        world.get_mut::<Velocity>(fighter).unwrap().x = -3.;
+        world.get_mut::<Health>(fighter).unwrap().damage(30);
+        *world.get_mut::<Facing>(fighter).unwrap() = Facing::Left;
+        world.entity_mut(fighter)
+            .remove::<(HitStun, IsBlocking)>()
+            .insert(BlockStun::new(4));
+        world.resource_mut::<MatchFlow>().timer = 10;
+
+        load_combat_state(&mut world, &snapshot);
+
+        assert_eq!(world.get::<Transform>(fighter).unwrap().translation.x, -100.);
+        assert_eq!(world.get::<Velocity>(fighter).unwrap().x, 2.);
+        assert_eq!(world.get::<Health>(fighter).unwrap().value(), 100);
+        assert_eq!(*world.get::<Facing>(fighter).unwrap(), Facing::Right);
+        assert!(world.get::<HitStun>(fighter).is_some());
+        assert!(world.get::<IsBlocking>(fighter).is_some());
+        assert!(world.get::<BlockStun>(fighter).is_none());
+        assert_eq!(world.resource::<MatchFlow>().timer, 600);
+    }
+
+    fn frame(buttons: &[InputButton]) -> InputFrame {
+        let mut frame = InputFrame::default();
+        for button in buttons {
+            frame.set(*button, true);
+        }
+        frame
+    }
+
+    //a combat step where every frame depends on the inputs of all the previous ones
+    fn step(mut fighters: Query<(&PlayerInput, &mut Velocity, &mut Transform, &mut Health)>) {
+        for (input, mut velocity, mut transform, mut health) in fighters.iter_mut() {
+            let push = match (input.pressed(InputButton::Left), input.pressed(InputButton::Right)) {
+                (true, false) => -1.,
+                (false, true) => 1.,
+                _ => 0.,
+            };
+            velocity.x = velocity.x * 0.5 + push;
+            transform.translation.x += velocity.x;
+            if input.pressed(InputButton::Light) {
+                health.damage(transform.translation.x.abs() as u32 % 7 + 1);
+            }
+        }
+    }
+
+    fn combat_world() -> (World, [Entity; NET_PLAYERS]) {
+        let mut world = World::new();
+        let mut schedule = Schedule::new(CombatStep);
+        schedule.add_systems(step);
+        world.add_schedule(schedule);
+
+        let fighters = [0, 1].map(|handle| world.spawn((
+            Player,
+            PlayerHandle(handle),
+            Transform::default(),
+            Velocity { x: 0., y: 0. },
+            Health::new(100),
+            PlayerAnimationManagement::default(),
+            PlayerInput::default(),
+            Facing::Right,
+        )).id());
+        (world, fighters)
+    }
+
+    fn fighter_state(world: &World, fighter: Entity) -> (f32, f32, u32) {
+        (
+            world.get::<Transform>(fighter).unwrap().translation.x,
+            world.get::<Velocity>(fighter).unwrap().x,
+            world.get::<Health>(fighter).unwrap().value(),
+        )
+    }
+
+    #[test]
+    fn late_remote_inputs_are_resimulated() {
+        let local_inputs: Vec<InputFrame> = (0..8)
+            .map(|i| if i % 3 == 0 { frame(&[InputButton::Light]) } else { frame(&[InputButton::Left]) })
+            .collect();
+        let remote_inputs = [
+            InputFrame::default(),
+            InputFrame::default(),
+            frame(&[InputButton::Right]),
+            frame(&[InputButton::Right, InputButton::Light]),
+            frame(&[InputButton::Right]),
+            frame(&[InputButton::Right]),
+            frame(&[InputButton::Left, InputButton::Light]),
+            InputFrame::default(),
+        ];
+
+        //both inputs known in advance, as on a local match
+        let (mut expected, fighters) = combat_world();
+        for (local, remote) in local_inputs.iter().zip(remote_inputs) {
+            for (fighter, input) in fighters.iter().zip([*local, remote]) {
+                expected.get_mut::<PlayerInput>(*fighter).unwrap().current = input;
+            }
+            expected.run_schedule(CombatStep);
+        }
+
+        let (mut world, fighters) = combat_world();
+        world.insert_resource(NetSession::new(NetConfig {
+            local_port: 0,
+            remote: "127.0.0.1:9".parse().unwrap(),
+            local_handle: 0,
+            input_delay: 0,
+            latency: Duration::ZERO,
+            packet_loss: 0.,
+        }).unwrap());
+        let play = |world: &mut World, local: InputFrame| {
+            world.get_mut::<PlayerInput>(fighters[0]).unwrap().current = local;
+            rollback_step(world);
+        };
+
+        //the remote player is predicted to stand still
+        for local in &local_inputs[..6] {
+            play(&mut world, *local);
+        }
+        assert_eq!(fighter_state(&world, fighters[1]), (0., 0., 100));
+
+        //its first inputs arrive late and differ from the prediction from frame 2
+        world.resource_mut::<NetSession>().add_packet(InputPacket { ack: None, start_frame: 0, inputs: remote_inputs[..6].to_vec() });
+        assert_eq!(world.resource::<NetSession>().rollback_to, Some(2));
+        play(&mut world, local_inputs[6]);
+
+        //frame 6 was predicted again, holding right, and is corrected by the next packet
+        world.resource_mut::<NetSession>().add_packet(InputPacket { ack: Some(8), start_frame: 6, inputs: remote_inputs[6..].to_vec() });
+        assert_eq!(world.resource::<NetSession>().rollback_to, Some(6));
+        play(&mut world, local_inputs[7]);
+
+        let session = world.resource::<NetSession>();
+        assert_eq!(session.frame, 8);
+        assert_eq!(session.remote_confirmed, 8);
+        assert!(session.predictions.is_empty());
+        for fighter in fighters {
+            assert_eq!(fighter_state(&world, fighter), fighter_state(&expected, fighter));
+        }
+    }
+
+    #[test]
+    fn matching_predictions_do_not_roll_back() {
+        let (mut world, _) = combat_world();
+        let mut session = NetSession::new(NetConfig {
+            local_port: 0,
+            remote: "127.0.0.1:9".parse().unwrap(),
+            local_handle: 1,
+            input_delay: 2,
+            latency: Duration::ZERO,
+            packet_loss: 0.,
+        }).unwrap();
+
+        //the input delay frames are confirmed as empty from the start
+        assert_eq!(session.remote_confirmed, 2);
+        for frame in 0..4 {
+            session.simulate(&mut world, frame);
+        }
+        assert_eq!(session.predictions.len(), 2);
+
+        session.add_packet(InputPacket { ack: None, start_frame: 2, inputs: vec![InputFrame::default(); 2] });
+        assert_eq!(session.rollback_to, None);
+        assert!(session.predictions.is_empty());
+        assert_eq!(session.remote_confirmed, 4);
+
+        //inputs already confirmed are ignored when they arrive again
+        session.add_packet(InputPacket { ack: None, start_frame: 0, inputs: vec![frame(&[InputButton::Up]); 4] });
+        assert_eq!(session.rollback_to, None);
+    }
+}
@@ -0,0 +1,365 @@
+use bevy::prelude::*;
+
+use crate::{components::*, controls::{Action, Controls}, training::Training, start_position, CombatSet, CombatStep, GameState, MatchSetup, NetSession};
+
+//how long the result of a round stays on screen before the next one
+const ROUND_OVER_FRAMES: u16 = 120;
+
+
+pub struct RoundPlugin {
+    //maximum number of rounds of a set, the first to win the majority takes it
+    pub rounds: u8,
+    pub round_seconds: u16,
+}
+
+impl Default for RoundPlugin {
+    fn default() -> Self {
+        Self { rounds: 3, round_seconds: 99 }
+    }
+}
+
+impl Plugin for RoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MatchRules {
+                rounds: self.rounds.max(1),
+                round_frames: u32::from(self.round_seconds) * u32::from(MAX_FRAME_RATE),
+            })
+            //nobody moves or gets hit while the result of a round is shown
+            .configure_sets(CombatStep, CombatSet::Input.run_if(round_in_progress))
+            .configure_sets(CombatStep, CombatSet::Hits.run_if(round_in_progress))
+            .add_event::<RestartRound>()
+            .add_systems(CombatStep, (restart_round, update_match).chain().in_set(CombatSet::Rounds))
+            .add_systems(OnEnter(GameState::InGame), (start_match, round_ui).in_set(MatchSetup))
+            .add_systems(Update, update_round_ui.run_if(in_state(GameState::InGame)))
+            .add_systems(OnEnter(GameState::GameEnded), update_round_ui)
+            .add_systems(Update, leave_match
+                .run_if(in_state(GameState::GameEnded))
+                .run_if(not(resource_exists::<NetSession>))
+            )
+            .add_systems(OnExit(GameState::GameEnded), end_match);
+    }
+}
+
+
+#[derive(Resource, Debug, Clone)]
+pub struct MatchRules {
+    pub rounds: u8,
+    pub round_frames: u32,
+}
+
+impl MatchRules {
+    pub fn wins_needed(&self) -> u8 {
+        self.rounds / 2 + 1
+    }
+}
+
+//puts everyone back to the start of the current round, keeping the rounds won
+#[derive(Event)]
+pub struct RestartRound;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundEnd {
+    KnockOut,
+    TimeOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundPhase {
+    Fighting,
+    //winner is None on a draw
+    Over { end: RoundEnd, winner: Option<usize>, frames: u16 },
+    //the set is decided, winner is None if it ended in a draw
+    Finished { winner: Option<usize> },
+}
+
+//state of the current set, it is part of the simulation
+//so it advances together with the combat step and gets rolled back with it
+#[derive(Resource, Debug, Clone)]
+pub struct MatchFlow {
+    pub round: u8,
+    //game frames left in the current round
+    pub timer: u32,
+    //rounds won, indexed by player handle
+    pub wins: Vec<u8>,
+    pub phase: RoundPhase,
+}
+
+impl MatchFlow {
+    pub fn new(rules: &MatchRules) -> Self {
+        Self {
+            round: 1,
+            timer: rules.round_frames,
+            wins: Vec::new(),
+            phase: RoundPhase::Fighting,
+        }
+    }
+
+    pub fn wins(&self, handle: usize) -> u8 {
+        self.wins.get(handle).copied().unwrap_or(0)
+    }
+
+    fn award(&mut self, handle: usize) {
+        if self.wins.len() <= handle {
+            self.wins.resize(handle + 1, 0);
+        }
+        self.wins[handle] += 1;
+    }
+
+    fn start_round(&mut self, rules: &MatchRules) {
+        self.timer = rules.round_frames;
+        self.phase = RoundPhase::Fighting;
+    }
+
+    pub fn seconds_left(&self) -> u32 {
+        self.timer.div_ceil(MAX_FRAME_RATE.into())
+    }
+}
+
+pub fn round_in_progress(flow: Option<Res<MatchFlow>>) -> bool {
+    flow.is_some_and(|flow| flow.phase == RoundPhase::Fighting)
+}
+
+fn start_match(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+) {
+    commands.insert_resource(MatchFlow::new(&rules));
+}
+
+//removes everything the match spawned
+pub fn end_match(
+    mut commands: Commands,
+    query: Query<Entity, Or<(With<Player>, With<MatchUi>, With<Camera2d>)>>,
+) {
+    query.iter().for_each(|entity| commands.entity(entity).despawn_recursive());
+    commands.remove_resource::<MatchFlow>();
+}
+
+//the fighters left with the most health win, everyone on a tie
+fn round_winners(fighters: &[(usize, &Health)]) -> Vec<usize> {
+    let best = fighters.iter().map(|(_, health)| health.value()).max().unwrap_or(0);
+    fighters.iter()
+        .filter(|(_, health)| health.value() == best)
+        .map(|(handle, _)| *handle)
+        .collect()
+}
+
+type RoundFighter = (
+    Entity, &'static PlayerHandle, &'static mut Health, &'static mut Transform,
+    &'static mut Velocity, &'static mut PlayerAnimationManagement, &'static mut Facing
+);
+
+//everyone goes back to the start, fully healed
+fn reset_fighters(commands: &mut Commands, query: &mut Query<RoundFighter, With<Player>>) {
+    let players = query.iter().count();
+    for (entity, handle, mut health, mut transform, mut velocity, mut animation, mut facing) in query.iter_mut() {
+        health.refill();
+        transform.translation.x = start_position(handle.0, players);
+        transform.translation.y = 0.;
+        *velocity = Velocity::default();
+        animation.interrupt();
+        *facing = Facing::default();
+        commands.entity(entity)
+            .remove::<(HitStun, HitStop, BlockStun, Hitboxes, AttackHits, IsBlocking, Combo, Juggle)>();
+    }
+}
+
+//read in the combat step rather than every render frame,
+//so a replay can record the game frame the restart happened on
+fn restart_round(
+    mut events: EventReader<RestartRound>,
+    rules: Res<MatchRules>,
+    flow: Option<ResMut<MatchFlow>>,
+    mut commands: Commands,
+    mut query: Query<RoundFighter, With<Player>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    let Some(mut flow) = flow else {
+        return;
+    };
+
+    reset_fighters(&mut commands, &mut query);
+    flow.start_round(&rules);
+}
+
+pub fn update_match(
+    rules: Res<MatchRules>,
+    mut flow: ResMut<MatchFlow>,
+    mut next_state: ResMut<NextState<GameState>>,
+    training: Option<Res<Training>>,
+    mut commands: Commands,
+    mut query: Query<RoundFighter, With<Player>>,
+) {
+    //training goes on without timer nor knock outs
+    if training.is_some() {
+        return;
+    }
+
+    match flow.phase {
+        RoundPhase::Fighting => {
+            flow.timer = flow.timer.saturating_sub(1);
+
+            let fighters: Vec<_> = query.iter()
+                .map(|(_, handle, health, ..)| (handle.0, health))
+                .collect();
+            let standing = fighters.iter().filter(|(_, health)| !health.is_ko()).count();
+            let end = if standing <= 1 && fighters.len() > 1 {
+                RoundEnd::KnockOut
+            } else if flow.timer == 0 {
+                RoundEnd::TimeOut
+            } else {
+                return;
+            };
+
+            let winners = round_winners(&fighters);
+            for handle in winners.iter() {
+                flow.award(*handle);
+            }
+            let winner = match winners[..] {
+                [handle] => Some(handle),
+                _ => None,
+            };
+            flow.phase = RoundPhase::Over { end, winner, frames: ROUND_OVER_FRAMES };
+
+            for (.., mut velocity, _, _) in query.iter_mut() {
+                velocity.x = 0.;
+            }
+        },
+        RoundPhase::Over { end, winner, frames } => {
+            if frames > 0 {
+                flow.phase = RoundPhase::Over { end, winner, frames: frames - 1 };
+                return;
+            }
+
+            let needed = rules.wins_needed();
+            let decided = flow.wins.iter().any(|wins| *wins >= needed);
+            if decided || flow.round >= rules.rounds {
+                //when nobody reached the majority the most rounds won takes the set
+                let most = flow.wins.iter().copied().max().unwrap_or(0);
+                let leaders: Vec<usize> = (0..flow.wins.len())
+                    .filter(|handle| flow.wins(*handle) == most)
+                    .collect();
+                let winner = match leaders[..] {
+                    [handle] => Some(handle),
+                    _ => None,
+                };
+                flow.phase = RoundPhase::Finished { winner };
+                next_state.set(GameState::GameEnded);
+                return;
+            }
+
+            reset_fighters(&mut commands, &mut query);
+            flow.round += 1;
+            flow.start_round(&rules);
+        },
+        RoundPhase::Finished { .. } => { },
+    }
+}
+
+
+//ui that lives as long as the match
+#[derive(Component)]
+pub struct MatchUi;
+
+#[derive(Component)]
+struct RoundText;
+
+fn round_ui(mut commands: Commands) {
+    commands.spawn((
+        RoundText,
+        MatchUi,
+        TextBundle::from_sections([
+            TextSection::new("", TextStyle { font_size: 40., ..default() }),
+            TextSection::new("", TextStyle { font_size: 60., ..default() }),
+        ])
+        .with_text_justify(JustifyText::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            justify_self: JustifySelf::Center,
+            top: Val::Px(0.),
+            ..default()
+        })
+    ));
+}
+
+fn update_round_ui(
+    flow: Option<Res<MatchFlow>>,
+    training: Option<Res<Training>>,
+    mut query: Query<&mut Text, With<RoundText>>,
+) {
+    let Some(flow) = flow else {
+        return;
+    };
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = if training.is_some() {
+            "Training\n".to_string()
+        } else {
+            format!("Round {}  {}\n", flow.round, flow.seconds_left())
+        };
+        text.sections[1].value = match flow.phase {
+            RoundPhase::Fighting => String::new(),
+            RoundPhase::Over { end, winner, .. } => {
+                let end = match end {
+                    RoundEnd::KnockOut => "K.O.",
+                    RoundEnd::TimeOut => "Time!",
+                };
+                match winner {
+                    Some(handle) => format!("{end}\nPlayer {} wins the round", handle + 1),
+                    None => format!("{end}\nDraw"),
+                }
+            },
+            RoundPhase::Finished { winner: Some(handle) } => format!("Player {} wins!", handle + 1),
+            RoundPhase::Finished { winner: None } => "Draw!".to_string(),
+        };
+    }
+}
+
+//any confirm button goes back to the character selection for a rematch
+fn leave_match(
+    controls: Res<Controls>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let gamepad_pressed = gamepads.iter().any(|gamepad| {
+        controls.button_just_pressed(&buttons, gamepad, Action::Confirm)
+            || controls.button_just_pressed(&buttons, gamepad, Action::Start)
+    });
+    let keyboard_pressed = controls.any_key_just_pressed(&keys, Action::Confirm)
+        || controls.any_key_just_pressed(&keys, Action::Start);
+
+    if gamepad_pressed || keyboard_pressed {
+        next_state.set(GameState::CharacterSelection);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_rounds_keep_their_length() {
+        let mut app = App::new();
+        app.add_plugins(RoundPlugin { rounds: 3, round_seconds: u16::MAX });
+        let rules = app.world.resource::<MatchRules>();
+        assert_eq!(rules.round_frames, u16::MAX as u32 * MAX_FRAME_RATE as u32);
+        assert_eq!(MatchFlow::new(rules).seconds_left(), u16::MAX as u32);
+    }
+
+    #[test]
+    fn seconds_left_rounds_up() {
+        let mut flow = MatchFlow::new(&MatchRules { rounds: 1, round_frames: 99 * MAX_FRAME_RATE as u32 });
+        assert_eq!(flow.seconds_left(), 99);
+        flow.timer -= 1;
+        assert_eq!(flow.seconds_left(), 99);
+        flow.timer = 1;
+        assert_eq!(flow.seconds_left(), 1);
+        flow.timer = 0;
+        assert_eq!(flow.seconds_left(), 0);
+    }
+}
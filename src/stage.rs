@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_common_assets::ron::RonAssetPlugin;
+
+use crate::{components::*, GameState, MatchSetup};
+
+const STAGE_PATH: &str = "stages/studio.stage.ron";
+//how fast the camera catches up with the fighters
+const CAMERA_SMOOTHING: f32 = 8.;
+
+
+pub struct StagePlugin;
+
+impl Plugin for StagePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Stage>()
+            .add_plugins(RonAssetPlugin::<Stage>::new(&["stage.ron"]))
+            .add_systems(OnEnter(GameState::Setup), load_stage)
+            .add_systems(OnEnter(GameState::InGame), setup_stage.in_set(MatchSetup))
+            .add_systems(Update, follow_fighters.run_if(in_state(GameState::InGame)));
+    }
+}
+
+
+#[derive(serde::Deserialize, Asset, TypePath, Resource, Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    //distance between the two walls, centered on the origin
+    pub width: f32,
+    pub camera: CameraOptions,
+}
+
+impl Stage {
+    pub fn left_wall(&self) -> f32 {
+        -self.width / 2.
+    }
+
+    pub fn right_wall(&self) -> f32 {
+        self.width / 2.
+    }
+}
+
+//zoom is the scale of the orthographic projection:
+//1 is one world unit per pixel, 2 shows twice as much of the stage
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CameraOptions {
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    //space kept between the fighters and the edge of the screen
+    pub margin: f32,
+}
+
+
+#[derive(Resource, Default)]
+pub struct StageHandle(pub Handle<Stage>);
+
+fn load_stage(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>
+) {
+    commands.insert_resource(StageHandle(asset_server.load(STAGE_PATH)));
+}
+
+//the stage data is fixed for the whole match
+fn setup_stage(
+    mut commands: Commands,
+    handle: Res<StageHandle>,
+    stages: Res<Assets<Stage>>,
+) {
+    if let Some(stage) = stages.get(&handle.0) {
+        info!("Fighting in {}", stage.name);
+        commands.insert_resource(stage.clone());
+    } else {
+        warn!("Stage {STAGE_PATH} is not loaded, fighters can leave the screen");
+    }
+}
+
+
+//keeps every fighter's pushbox between the walls
+pub fn clamp_to_stage(
+    stage: Option<Res<Stage>>,
+    mut query: Query<(&mut Transform, &Pushbox, &Facing)>,
+) {
+    let Some(stage) = stage else {
+        return;
+    };
+
+    for (mut transform, pushbox, facing) in query.iter_mut() {
+        let offset = pushbox.offset(*facing).x;
+        let half = pushbox.length / 2.;
+        let min = stage.left_wall() + half - offset;
+        let max = stage.right_wall() - half - offset;
+        transform.translation.x = transform.translation.x.clamp(min, max.max(min));
+    }
+}
+
+
+//centers the camera between the fighters and zooms out to keep all of them on screen
+fn follow_fighters(
+    time: Res<Time>,
+    stage: Option<Res<Stage>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    fighters: Query<&Transform, (With<Player>, Without<Camera2d>)>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    let (Some(stage), Ok(window)) = (stage, windows.get_single()) else {
+        return;
+    };
+    let Ok((mut camera_transform, mut projection)) = camera.get_single_mut() else {
+        return;
+    };
+
+    let mut xs = fighters.iter().map(|transform| transform.translation.x);
+    let Some(first) = xs.next() else {
+        return;
+    };
+    let (min_x, max_x) = xs.fold((first, first), |(min, max), x| (min.min(x), max.max(x)));
+    let count = fighters.iter().count() as f32;
+    let midpoint = fighters.iter().map(|transform| transform.translation.truncate()).sum::<Vec2>() / count;
+
+    let options = &stage.camera;
+    let spread = max_x - min_x + options.margin * 2.;
+    let zoom = (spread / window.width()).clamp(options.min_zoom, options.max_zoom);
+
+    //never show what is behind the walls
+    let half_view = window.width() * zoom / 2.;
+    let target_x = if half_view * 2. < stage.width {
+        midpoint.x.clamp(stage.left_wall() + half_view, stage.right_wall() - half_view)
+    } else {
+        0.
+    };
+
+    let smoothing = 1. - (-CAMERA_SMOOTHING * time.delta_seconds()).exp();
+    camera_transform.translation.x += (target_x - camera_transform.translation.x) * smoothing;
+    camera_transform.translation.y += (midpoint.y - camera_transform.translation.y) * smoothing;
+    projection.scale += (zoom - projection.scale) * smoothing;
+}
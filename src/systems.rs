@@ -1,66 +1,107 @@
 use bevy::prelude::*;
 use bevy::input::gamepad::{GamepadConnection, GamepadEvent};
 
-use crate::components::*;
+use crate::{components::*, controls::Controls, cpu::CpuController, stage::Stage, training::Dummy, CombatStep};
+
+//share of the pushback speed kept every game frame of stun
+const PUSHBACK_FRICTION: f32 = 0.85;
 
 pub fn execute_animations(
-    time: Res<Time>,
-    mut query: Query<(&mut PlayerAnimationManagement, &mut TextureAtlas)>,
+    mut query: Query<(&mut PlayerAnimationManagement, &mut TextureAtlas), Without<HitStop>>,
 ) {
     for (mut player_animation, mut atlas) in &mut query {
         //scope to drop automatically anim so the mutable reference stop existing
         {
             //get current animation data
             let (animation_manager, _) = &mut player_animation.get_current_animation_mut();
-            // we track how long the current sprite has been displayed for
-            animation_manager.tick(time.delta());
-        }
-
-
-        let (animation_manager, _) = player_animation.get_current_animation().clone();
-        // If it has been displayed for the user-defined amount of time (fps)...
-        if animation_manager.is_just_finished() {
-            if animation_manager.is_last_frame() {
-                // ...and it IS the last frame, then we move back to the first frame and stop.
-                {
-                    let (animation_manager, _) = &mut player_animation.get_current_animation_mut();
-                    animation_manager.reset(MAX_FRAME_RATE);
-                }                
-                player_animation.shift();
-                let (animation_manager, _) = &mut player_animation.get_current_animation_mut();
-                animation_manager.reset(MAX_FRAME_RATE);
-                atlas.index = animation_manager.current_sprite_index().unwrap().into();
-            } else {
-                let (animation_manager, _) = &mut player_animation.get_current_animation_mut();
-                // ...and it is NOT the last frame, then we move to the next frame...
-                animation_manager.next_frame(MAX_FRAME_RATE);
-                atlas.index = animation_manager.current_sprite_index().unwrap().into();
-                // ...and reset the frame timer to start counting all over again
-                
-                animation_manager.start_timer(MAX_FRAME_RATE);
-            }
+            // every simulation step is exactly one game frame
+            animation_manager.tick();
+        }
+
+        // If it has been displayed for all of its frames...
+        if player_animation.get_current_animation().0.is_finished() {
+            // ...then we move to the buffered or default animation
+            player_animation.shift();
         }
+
+        let (animation_manager, _) = player_animation.get_current_animation();
+        atlas.index = animation_manager.current_sprite_index().unwrap().into();
     }
 }
 
 
 
+//advances the combat simulation by one game frame
+pub fn run_combat_step(world: &mut World) {
+    world.run_schedule(CombatStep);
+}
 
+
+//samples the keyboard once per render frame,
+//the simulation reads it on the next fixed step
 pub fn keyboard_input_system(
-    time: Res<Time>,
-    mut commands: Commands,
+    controls: Res<Controls>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut query: Query<(Option<&InputController>, &mut PlayerInput), (With<Player>, Without<CpuController>, Without<Dummy>)>,
+) {
+    for (input_controller, mut input) in query.iter_mut() {
+        //netplay fighters have no device, the local one plays on the whole keyboard
+        let layout = match input_controller {
+            Some(InputController::Keyboard(layout)) => *layout,
+            Some(InputController::Gamepad(_)) => continue,
+            None => KeyboardLayout::Full,
+        };
+        input.current = controls.keyboard_frame(&keyboard_input, layout);
+    }
+}
+
+
+//samples the gamepad of every fighter that has one
+pub fn gamepad_input_system(
+    controls: Res<Controls>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut query: Query<(&InputController, &mut PlayerInput), With<Player>>,
+) {
+    for (input_controller, mut input) in query.iter_mut() {
+        if let InputController::Gamepad(gamepad) = input_controller {
+            input.current = controls.gamepad_frame(&buttons, &axes, *gamepad);
+        }
+    }
+}
+
+
+pub fn apply_player_input(
+    mut commands: Commands,
     mut query: Query<(Entity,
-        &mut Transform, &mut PlayerAnimationManagement, &Speed,
-        &mut Velocity,
-        ), Without<HitStun>>,
+        &Transform, &mut PlayerAnimationManagement, &Speed,
+        &mut Velocity, &mut PlayerInput, &Facing, Has<HitStun>, Has<BlockStun>, Has<HitStop>, Has<Juggle>
+        )>,
 ) {
     //when pressing a button
     //it needs to ask/check if move is legal
     //it is legal if no move is active 
     //or if it falls withing cancel and linking rules
-    for (entity, mut transform, mut animation, speed, mut velocity) in query.iter_mut() {
-        if keyboard_input.pressed(KeyCode::KeyW) { 
+    for (entity, transform, mut animation, speed, mut velocity, mut input, facing, in_hitstun, in_blockstun, in_hitstop, juggled) in query.iter_mut() {
+        //frozen fighters keep their velocity for when the hitstop ends,
+        //juggled ones follow their arc until they land
+        if in_hitstop || juggled {
+            input.advance();
+            continue;
+        }
+
+        //stunned players keep blocking if they were
+        if in_hitstun || in_blockstun {
+            //the pushback slides the fighter until it stops
+            velocity.x *= PUSHBACK_FRICTION;
+            if velocity.x.abs() < 0.1 {
+                velocity.x = 0.;
+            }
+            input.advance();
+            continue;
+        }
+
+        if input.pressed(InputButton::Up) { 
             //implement jump
             //check if is grounded
             if transform.translation.y == 0. && animation.request_animation(AnimationState::Jump) {
@@ -70,39 +111,182 @@ pub fn keyboard_input_system(
         }
 
         //checks for button pressed and request response
-        if keyboard_input.pressed(KeyCode::KeyA) && animation.request_animation(AnimationState::Backward) {
-            velocity.x = -speed.0;           
-        } else if keyboard_input.pressed(KeyCode::KeyD) && animation.request_animation(AnimationState::Forward) {
-            velocity.x = speed.0;           
+        if input.back_pressed(*facing) && animation.request_animation(AnimationState::Backward) {
+            velocity.x = -speed.0 * facing.sign();           
+        } else if input.forward_pressed(*facing) && animation.request_animation(AnimationState::Forward) {
+            velocity.x = speed.0 * facing.sign();           
         } else {
             velocity.x = 0.;
         }
 
-        if keyboard_input.pressed(KeyCode::KeyS) {
+        if input.pressed(InputButton::Down) {
             animation.request_animation(AnimationState::Crouch);
         }
 
-        if keyboard_input.pressed(KeyCode::KeyQ) && animation.request_animation(AnimationState::Block) {
+        if input.pressed(InputButton::Block) && animation.request_animation(AnimationState::Block) {
             commands.entity(entity).insert(IsBlocking);
         } else {
             commands.entity(entity).remove::<IsBlocking>();
         }
 
-        if keyboard_input.just_pressed(KeyCode::KeyZ) {
-            animation.request_animation(AnimationState::LightAttack);
+        if input.just_pressed(InputButton::Parry) {
+            animation.request_animation(AnimationState::Parry);
+        }
+
+        if input.just_pressed(InputButton::Fake) {
+            animation.cancel_startup();
+        }
+
+        if input.just_pressed(InputButton::Light) {
+            let state = attack_variant(&animation, AnimationState::LightAttack, input.attack_direction(*facing));
+            animation.request_animation(state);
+        }
+
+        if input.just_pressed(InputButton::Heavy) {
+            let state = attack_variant(&animation, AnimationState::HeavyAttack, input.attack_direction(*facing));
+            animation.request_animation(state);
+        }
+
+        input.advance();
+    }
+}
+
+//pushes overlapping fighters apart:
+//whoever walks into a standing opponent pushes it,
+//when both walk forward (or nobody does) the push is split
+//and a fighter stuck in the corner pushes the other one back
+pub fn resolve_pushboxes(
+    stage: Option<Res<Stage>>,
+    mut query: Query<(Entity, &mut Transform, &Pushbox, &Facing, &Velocity)>,
+) {
+    let (left_wall, right_wall) = stage
+        .map(|stage| (stage.left_wall(), stage.right_wall()))
+        .unwrap_or((f32::NEG_INFINITY, f32::INFINITY));
+    //how far a body can be pushed before hitting a wall
+    let room = |center: Vec2, half: Vec2, direction: f32| {
+        if direction < 0. {
+            (center.x - half.x - left_wall).max(0.)
+        } else {
+            (right_wall - center.x - half.x).max(0.)
+        }
+    };
+
+    let bodies: Vec<(Entity, Vec2, Vec2, f32)> = query.iter()
+        .map(|(entity, transform, pushbox, facing, velocity)| {
+            let center = transform.translation.truncate() + pushbox.offset(*facing);
+            (entity, center, pushbox.size() / 2., velocity.x)
+        })
+        .collect();
+
+    let mut pushes: Vec<(Entity, f32)> = Vec::new();
+    for (i, (a, a_center, a_half, a_velocity)) in bodies.iter().enumerate() {
+        for (b, b_center, b_half, b_velocity) in bodies.iter().skip(i + 1) {
+            let overlap_x = a_half.x + b_half.x - (a_center.x - b_center.x).abs();
+            let overlap_y = a_half.y + b_half.y - (a_center.y - b_center.y).abs();
+            if overlap_x <= 0. || overlap_y <= 0. {
+                continue;
+            }
+
+            //direction from a to b
+            let direction = if a_center.x <= b_center.x { 1. } else { -1. };
+            let a_advancing = a_velocity * direction > 0.;
+            let b_advancing = -b_velocity * direction > 0.;
+            let a_share = match (a_advancing, b_advancing) {
+                (true, false) => 0.,
+                (false, true) => 1.,
+                _ => 0.5,
+            };
+
+            let mut a_push = overlap_x * a_share;
+            let mut b_push = overlap_x - a_push;
+            let a_room = room(*a_center, *a_half, -direction);
+            if a_push > a_room {
+                b_push += a_push - a_room;
+                a_push = a_room;
+            }
+            let b_room = room(*b_center, *b_half, direction);
+            if b_push > b_room {
+                a_push += b_push - b_room;
+                b_push = b_room;
+            }
+
+            pushes.push((*a, -direction * a_push));
+            pushes.push((*b, direction * b_push));
         }
+    }
 
-        if keyboard_input.just_pressed(KeyCode::KeyX) {
-            animation.request_animation(AnimationState::HeavyAttack);
+    for (entity, push) in pushes {
+        if let Ok((_, mut transform, _, _, _)) = query.get_mut(entity) {
+            transform.translation.x += push;
         }
+    }
+}
+
+//fighters turn towards the closest opponent,
+//but never in the middle of an attack
+pub fn update_facing(
+    mut query: Query<(Entity, &Transform, &PlayerAnimationManagement, &mut Facing, &mut Sprite)>,
+) {
+    let positions: Vec<(Entity, f32)> = query.iter()
+        .map(|(entity, transform, _, _, _)| (entity, transform.translation.x))
+        .collect();
+
+    for (entity, transform, animation, mut facing, mut sprite) in query.iter_mut() {
+        let x = transform.translation.x;
+        let opponent = positions.iter()
+            .filter(|(other, _)| *other != entity)
+            .min_by(|(_, a), (_, b)| (a - x).abs().total_cmp(&(b - x).abs()));
 
-        let delta_time = time.delta_seconds();
+        if let Some((_, opponent_x)) = opponent {
+            if !animation.state.is_attack() && *opponent_x != x {
+                *facing = if *opponent_x > x { Facing::Right } else { Facing::Left };
+            }
+        }
+
+        sprite.flip_x = *facing == Facing::Left;
+    }
+}
+
+//picks the directional variant of an attack,
+//falling back to the normal one when the character does not have it
+fn attack_variant(
+    animation: &PlayerAnimationManagement,
+    attack: fn(AttackDirection) -> AnimationState,
+    direction: AttackDirection,
+) -> AnimationState {
+    let state = attack(direction);
+    if animation.has_animation(&state) {
+        state
+    } else {
+        attack(AttackDirection::Normal)
+    }
+}
+
+//juggled fighters get back control once they touch the ground
+pub fn land_juggled(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Transform, &mut Velocity), (With<Juggle>, Without<HitStop>)>,
+) {
+    for (entity, transform, mut velocity) in query.iter_mut() {
+        if transform.translation.y <= 0. && velocity.y <= 0. {
+            velocity.x = 0.;
+            commands.entity(entity).remove::<Juggle>();
+        }
+    }
+}
+
+//integrates gravity and velocity over one game frame
+pub fn apply_velocity(
+    mut query: Query<(&mut Transform, &mut Velocity), Without<HitStop>>,
+) {
+    let frame_time = 1. / MAX_FRAME_RATE as f32;
+    for (mut transform, mut velocity) in query.iter_mut() {
         let gravity_coef = 15.;
-        velocity.y -= gravity_coef * delta_time; 
+        velocity.y -= gravity_coef * frame_time; 
 
         // Update position based on velocity
-        transform.translation.x += velocity.x * delta_time * 100.;
-        transform.translation.y += velocity.y * delta_time * 100.;
+        transform.translation.x += velocity.x * frame_time * 100.;
+        transform.translation.y += velocity.y * frame_time * 100.;
 
         // Ensure player doesn't fall through the ground
         if transform.translation.y < 0. {
@@ -133,55 +317,15 @@ pub fn gamepad_connections(
                     ev_conn.gamepad, info.name,
                 );
                 // if we don't have any gamepad yet, use this one
-                commands.spawn(InputController(ev_conn.gamepad));
+                commands.spawn(InputController::Gamepad(ev_conn.gamepad));
             }
             GamepadConnection::Disconnected => {
                 debug!("Lost connection with gamepad: {:?}", ev_conn.gamepad);
                 // if it's the one we previously used for the player, remove it:
-                if let Some((e, _)) = query.iter().find(|(_, x)| x.0 == ev_conn.gamepad) {
+                if let Some((e, _)) = query.iter().find(|(_, x)| **x == InputController::Gamepad(ev_conn.gamepad)) {
                     commands.entity(e).despawn()
                 }
             }
         }
     }
 }
-
-#[derive(Component)]
-pub struct HealthBar(pub Gamepad);
-
-pub fn health_bar(
-    mut commands: Commands,
-    query: Query<(&Name, &InputController, &Health), With<Player>>
-) {
-    let player_number = query.iter().count();
-    let division = 100 / player_number;
-    for (name, input_controller, health) in &query {
-        commands.spawn(NodeBundle {
-            style: Style {
-                justify_self: JustifySelf::Start,
-                ..default()
-            },
-            ..default()
-        })
-        .with_children(|parent| {
-            parent.spawn((
-                HealthBar(input_controller.0),
-                NodeBundle {
-                    style: Style {
-                        width: Val::Percent(division as f32),
-                        height: Val::Px(100.0),
-                        position_type: PositionType::Relative,
-                        top: Val::Px(10.),
-                        left: Val::Px(10.),
-                        ..default()
-                    },
-                    ..default()
-                }
-                )
-            );
-            parent.spawn(
-                TextBundle::from_section(name, TextStyle::default())
-            );
-        });
-    }
-}
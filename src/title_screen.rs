@@ -1,6 +1,10 @@
 use bevy::prelude::*;
 
-use crate::{GameState, spawn_camera};
+use crate::{GameState, spawn_camera, controls::{Action, Controls}, cpu::{CpuLevel, CpuOpponent}, training::Training};
+
+const OPTION_COLOR: Color = Color::WHITE;
+const SELECTED_COLOR: Color = Color::YELLOW;
+
 pub struct TitleScreenPlugin;
 
 impl Plugin for TitleScreenPlugin {
@@ -12,9 +16,12 @@ impl Plugin for TitleScreenPlugin {
                 title_screen_music,
             )
         )
-        .add_systems(Update, gamepad_input.run_if(in_state(GameState::TitleScreen)))
+        .add_systems(Update, (menu_input, highlight_selected_option)
+            .chain()
+            .run_if(in_state(GameState::TitleScreen))
+        )
         .add_systems(OnExit(GameState::TitleScreen), (
-                despawn_all_with::<Text>,
+                despawn_all_with::<TitleScreenUi>,
                 despawn_all_with::<Camera2d>,
                 despawn_all_with::<IntroSound>,
             )
@@ -25,6 +32,45 @@ impl Plugin for TitleScreenPlugin {
 #[derive(Component)]
 struct IntroSound;
 
+#[derive(Component)]
+struct TitleScreenUi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TitleOption {
+    Play,
+    VersusCpu,
+    Training,
+    Options,
+}
+
+impl TitleOption {
+    const ALL: [TitleOption; 4] = [
+        TitleOption::Play,
+        TitleOption::VersusCpu,
+        TitleOption::Training,
+        TitleOption::Options,
+    ];
+
+    fn label(&self, level: CpuLevel) -> String {
+        match self {
+            TitleOption::Play => "Play".to_string(),
+            TitleOption::VersusCpu => format!("Versus CPU: < {} >", level.label()),
+            TitleOption::Training => "Training".to_string(),
+            TitleOption::Options => "Options".to_string(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct TitleMenu {
+    selected: usize,
+    //back and forward change it while the cpu option is selected
+    level: CpuLevel,
+}
+
+#[derive(Component)]
+struct TitleMenuOption(usize);
+
 fn title_screen_music(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn((
         IntroSound,
@@ -39,21 +85,44 @@ fn title_screen_ui(
     asset_server: Res<AssetServer>,
     mut commands: Commands
 ) {
+    commands.init_resource::<TitleMenu>();
     commands.spawn((
-        TextBundle::from_section("Supa Studio Fighter X", 
-                                 TextStyle { 
-                                     font_size: 150., 
-                                     font: asset_server.load("fonts/Act_Of_Rejection.ttf"),
-                                     ..default() 
-                                 }
-        )
-        .with_text_justify(JustifyText::Center)
-        .with_style(Style {
-            justify_self: JustifySelf::Center,
-            align_self: AlignSelf::Center,
+        TitleScreenUi,
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.),
+                ..default()
+            },
             ..default()
-        })
-    ));
+        },
+    ))
+    .with_children(|parent| {
+        parent.spawn(
+            TextBundle::from_section("Supa Studio Fighter X", 
+                                     TextStyle { 
+                                         font_size: 150., 
+                                         font: asset_server.load("fonts/Act_Of_Rejection.ttf"),
+                                         ..default() 
+                                     }
+            )
+            .with_text_justify(JustifyText::Center)
+        );
+        for (i, option) in TitleOption::ALL.iter().enumerate() {
+            parent.spawn((
+                TitleMenuOption(i),
+                TextBundle::from_section(option.label(CpuLevel::default()), TextStyle {
+                    font_size: 40.,
+                    color: OPTION_COLOR,
+                    ..default()
+                }),
+            ));
+        }
+    });
 }
 
 fn despawn_all_with<C: Component>(
@@ -61,28 +130,72 @@ fn despawn_all_with<C: Component>(
     mut commands: Commands,
 )
 {
-    query.iter().for_each(|x| commands.entity(x).despawn());
+    query.iter().for_each(|x| commands.entity(x).despawn_recursive());
 }
 
 
-fn gamepad_input(
+//every keyboard and gamepad can move through the menu
+fn menu_input(
     mut commands: Commands,
+    controls: Res<Controls>,
+    mut menu: ResMut<TitleMenu>,
     gamepads: Res<Gamepads>,
     mut next_state: ResMut<NextState<GameState>>,
     buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
 ) {
-    for gamepad in gamepads.iter() {
-        let valid_keys = [
-            GamepadButton { gamepad, button_type: GamepadButtonType::East },
-            GamepadButton { gamepad, button_type: GamepadButtonType::West },
-            GamepadButton { gamepad, button_type: GamepadButtonType::South },
-            GamepadButton { gamepad, button_type: GamepadButtonType::North },
-        ];
-        if buttons.any_just_pressed(valid_keys) {
+    let pressed = |action| controls.any_key_just_pressed(&keys, action)
+        || gamepads.iter().any(|gamepad| controls.button_just_pressed(&buttons, gamepad, action));
+
+    let options = TitleOption::ALL.len();
+    if pressed(Action::Up) {
+        menu.selected = (menu.selected + options - 1) % options;
+    }
+    if pressed(Action::Down) {
+        menu.selected = (menu.selected + 1) % options;
+    }
+    if TitleOption::ALL[menu.selected] == TitleOption::VersusCpu {
+        if pressed(Action::Forward) {
+            menu.level = menu.level.next();
+        } else if pressed(Action::Back) {
+            menu.level = menu.level.prev();
+        }
+    }
+    if !pressed(Action::Confirm) && !pressed(Action::Start) {
+        return;
+    }
+
+    match TitleOption::ALL[menu.selected] {
+        TitleOption::Play => {
             commands.remove_resource::<ClearColor>();
+            commands.remove_resource::<CpuOpponent>();
+            commands.remove_resource::<Training>();
             next_state.set(GameState::CharacterSelection)
-        }
+        },
+        TitleOption::VersusCpu => {
+            commands.remove_resource::<ClearColor>();
+            commands.remove_resource::<Training>();
+            commands.insert_resource(CpuOpponent(menu.level));
+            next_state.set(GameState::CharacterSelection)
+        },
+        TitleOption::Training => {
+            commands.remove_resource::<ClearColor>();
+            commands.remove_resource::<CpuOpponent>();
+            commands.insert_resource(Training::default());
+            next_state.set(GameState::CharacterSelection)
+        },
+        TitleOption::Options => next_state.set(GameState::Options),
     }
 }
 
-
+fn highlight_selected_option(
+    menu: Res<TitleMenu>,
+    mut query: Query<(&TitleMenuOption, &mut Text)>,
+) {
+    for (option, mut text) in query.iter_mut() {
+        let color = if option.0 == menu.selected { SELECTED_COLOR } else { OPTION_COLOR };
+        let section = &mut text.sections[0];
+        section.value = TitleOption::ALL[option.0].label(menu.level);
+        section.style.color = color;
+    }
+}
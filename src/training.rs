@@ -0,0 +1,527 @@
+use bevy::prelude::*;
+
+use crate::{
+    components::*, controls::{Action, Controls}, fighter_bundle, start_position, spawn_player_after_selection,
+    round::MatchUi,
+    CombatSet, CombatStep, GameState, MatchSetup,
+};
+
+pub const TRAINING_SLOTS: usize = 4;
+//game frames without taking damage before the health comes back
+const REFILL_FRAMES: u8 = 60;
+//game frames the dummy keeps blocking once it recovers from a hit
+const BLOCK_AFTER_HIT_FRAMES: u8 = 30;
+//a recording stops by itself after this many game frames
+const MAX_RECORDING_FRAMES: usize = 10 * MAX_FRAME_RATE as usize;
+
+
+//a session without rounds against a dummy that can copy the player
+pub struct TrainingPlugin;
+
+impl Plugin for TrainingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), (
+                    spawn_dummy.after(spawn_player_after_selection),
+                    training_ui,
+                )
+                .run_if(resource_exists::<Training>)
+                .in_set(MatchSetup)
+            )
+            .add_systems(Update, (training_controls, update_training_ui)
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<Training>)
+            )
+            .add_systems(CombatStep, (
+                    dummy_input.before(CombatSet::Input),
+                    refill_health.in_set(CombatSet::Rounds),
+                )
+                .run_if(resource_exists::<Training>)
+            )
+            .add_systems(OnExit(GameState::InGame), stop_dummy.run_if(resource_exists::<Training>));
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DummyBehavior {
+    #[default]
+    Stand,
+    BlockAll,
+    BlockAfterFirstHit,
+    Jump,
+}
+
+impl DummyBehavior {
+    const ALL: [DummyBehavior; 4] = [
+        DummyBehavior::Stand,
+        DummyBehavior::BlockAll,
+        DummyBehavior::BlockAfterFirstHit,
+        DummyBehavior::Jump,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DummyBehavior::Stand => "Stand",
+            DummyBehavior::BlockAll => "Block all",
+            DummyBehavior::BlockAfterFirstHit => "Block after first hit",
+            DummyBehavior::Jump => "Jump",
+        }
+    }
+
+    pub fn next(&self) -> DummyBehavior {
+        let i = Self::ALL.iter().position(|behavior| behavior == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn prev(&self) -> DummyBehavior {
+        let i = Self::ALL.iter().position(|behavior| behavior == self).unwrap_or(0);
+        Self::ALL[(i + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone)]
+enum DummyMode {
+    //follows the behavior
+    Idle,
+    //the fighter of the player stands still, its inputs move the dummy
+    Recording { recorder: Entity, frames: Vec<InputFrame> },
+    Playback { frame: usize },
+}
+
+//settings and recordings of the training, kept between sessions until the title screen
+#[derive(Resource, Debug, Clone)]
+pub struct Training {
+    pub behavior: DummyBehavior,
+    pub slot: usize,
+    //inputs stored as if the dummy was facing right
+    slots: Vec<Vec<InputFrame>>,
+    mode: DummyMode,
+    block_frames: u8,
+}
+
+impl Default for Training {
+    fn default() -> Self {
+        Self {
+            behavior: DummyBehavior::default(),
+            slot: 0,
+            slots: vec![Vec::new(); TRAINING_SLOTS],
+            mode: DummyMode::Idle,
+            block_frames: 0,
+        }
+    }
+}
+
+impl Training {
+    pub fn next_slot(&mut self) {
+        self.slot = (self.slot + 1) % TRAINING_SLOTS;
+    }
+
+    pub fn prev_slot(&mut self) {
+        self.slot = (self.slot + TRAINING_SLOTS - 1) % TRAINING_SLOTS;
+    }
+
+    pub fn slot_seconds(&self, slot: usize) -> f32 {
+        self.slots[slot].len() as f32 / MAX_FRAME_RATE as f32
+    }
+
+    //a second press stores what was recorded in the current slot
+    fn toggle_recording(&mut self, recorder: Entity) {
+        self.mode = match std::mem::replace(&mut self.mode, DummyMode::Idle) {
+            DummyMode::Recording { frames, .. } => {
+                self.slots[self.slot] = frames;
+                DummyMode::Idle
+            },
+            _ => DummyMode::Recording { recorder, frames: Vec::new() },
+        };
+    }
+
+    //a second press stops the playback, an empty slot does nothing
+    fn toggle_playback(&mut self) {
+        self.mode = match self.mode {
+            DummyMode::Playback { .. } => DummyMode::Idle,
+            _ if self.slots[self.slot].is_empty() => DummyMode::Idle,
+            _ => DummyMode::Playback { frame: 0 },
+        };
+    }
+
+    //the recorder and the playback belong to the session that started them,
+    //what was being recorded is kept in the current slot
+    fn stop(&mut self) {
+        if let DummyMode::Recording { frames, .. } = std::mem::replace(&mut self.mode, DummyMode::Idle) {
+            self.slots[self.slot] = frames;
+        }
+        self.block_frames = 0;
+    }
+
+    fn status(&self) -> String {
+        let mode = match &self.mode {
+            DummyMode::Idle => String::new(),
+            DummyMode::Recording { frames, .. } => format!("  Recording {:.1}s", frames.len() as f32 / MAX_FRAME_RATE as f32),
+            DummyMode::Playback { .. } => "  Playing".to_string(),
+        };
+        format!(
+            "Dummy: {}  Slot {}/{} ({:.1}s){mode}",
+            self.behavior.label(),
+            self.slot + 1,
+            TRAINING_SLOTS,
+            self.slot_seconds(self.slot),
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct Dummy;
+
+//game frames left before the health of the fighter is refilled
+#[derive(Component)]
+struct Refill(u8);
+
+#[derive(Component)]
+struct TrainingText;
+
+
+//the dummy fights with the character of the first player
+fn spawn_dummy(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    characters: Res<Assets<Character>>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    mut fighters: Query<(Entity, &PlayerHandle, &CharacterAsset, &mut Transform), With<Player>>,
+) {
+    let Some((_, _, asset, _)) = fighters.iter().min_by_key(|(_, handle, ..)| handle.0) else {
+        return;
+    };
+    let character_id = asset.0;
+    let Some(character) = characters.get(character_id) else {
+        return;
+    };
+
+    let handle = fighters.iter().count();
+    let players = handle + 1;
+    for (entity, fighter_handle, _, mut transform) in fighters.iter_mut() {
+        transform.translation.x = start_position(fighter_handle.0, players);
+        commands.entity(entity).insert(Refill(0));
+    }
+
+    let x = start_position(handle, players);
+    commands.spawn((
+        fighter_bundle(character, character_id, handle, x, &asset_server, &mut texture_atlas_layouts),
+        Dummy,
+        Refill(0),
+    ));
+}
+
+fn training_ui(mut commands: Commands) {
+    commands.spawn((
+        TrainingText,
+        MatchUi,
+        TextBundle::from_section("", TextStyle { font_size: 30., ..default() })
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                justify_self: JustifySelf::Center,
+                bottom: Val::Px(10.),
+                ..default()
+            })
+    ));
+}
+
+fn update_training_ui(
+    training: Res<Training>,
+    mut query: Query<&mut Text, With<TrainingText>>,
+) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = training.status();
+    }
+}
+
+//Record and Playback work from the device of any player
+fn training_controls(
+    controls: Res<Controls>,
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut training: ResMut<Training>,
+    players: Query<(Entity, &InputController), With<Player>>,
+) {
+    for (entity, input_controller) in players.iter() {
+        let pressed = |action| controls.just_pressed(*input_controller, &keys, &buttons, action);
+        if pressed(Action::Record) {
+            training.toggle_recording(entity);
+        } else if pressed(Action::Playback) {
+            training.toggle_playback();
+        }
+    }
+}
+
+//pausing or leaving the match ends the recording and the playback
+fn stop_dummy(mut training: ResMut<Training>) {
+    training.stop();
+}
+
+//left and right swapped, to turn recordings around with the facing of the dummy
+fn mirrored(frame: InputFrame) -> InputFrame {
+    let mut mirrored = frame;
+    mirrored.set(InputButton::Left, frame.pressed(InputButton::Right));
+    mirrored.set(InputButton::Right, frame.pressed(InputButton::Left));
+    mirrored
+}
+
+fn dummy_input(
+    training: ResMut<Training>,
+    mut dummies: Query<(&mut PlayerInput, &Facing, Has<HitStun>, Has<BlockStun>, Has<Juggle>), With<Dummy>>,
+    mut players: Query<&mut PlayerInput, (With<Player>, Without<Dummy>)>,
+) {
+    let Ok((mut input, facing, in_hitstun, in_blockstun, juggled)) = dummies.get_single_mut() else {
+        return;
+    };
+    let training = training.into_inner();
+    let relative = |frame| if *facing == Facing::Left { mirrored(frame) } else { frame };
+
+    if in_hitstun || in_blockstun || juggled {
+        training.block_frames = BLOCK_AFTER_HIT_FRAMES;
+    } else {
+        training.block_frames = training.block_frames.saturating_sub(1);
+    }
+
+    let mut stop_recording = false;
+    match &mut training.mode {
+        DummyMode::Recording { recorder, frames } => match players.get_mut(*recorder) {
+            Ok(mut recorder_input) => {
+                input.current = recorder_input.current;
+                recorder_input.current = InputFrame::default();
+                frames.push(relative(input.current));
+                stop_recording = frames.len() >= MAX_RECORDING_FRAMES;
+            },
+            //the recording fighter is gone, the dummy goes back to its behavior
+            Err(_) => training.mode = DummyMode::Idle,
+        },
+        DummyMode::Playback { frame } => {
+            let recorded = training.slots[training.slot].get(*frame).copied();
+            match recorded {
+                Some(recorded) => {
+                    input.current = relative(recorded);
+                    *frame += 1;
+                },
+                None => training.mode = DummyMode::Idle,
+            }
+        },
+        DummyMode::Idle => {},
+    }
+    if stop_recording {
+        if let DummyMode::Recording { recorder, .. } = training.mode {
+            training.toggle_recording(recorder);
+        }
+    }
+    if !matches!(training.mode, DummyMode::Idle) {
+        return;
+    }
+
+    let mut frame = InputFrame::default();
+    match training.behavior {
+        DummyBehavior::Stand => {},
+        DummyBehavior::BlockAll => frame.set(InputButton::Block, true),
+        DummyBehavior::BlockAfterFirstHit => frame.set(InputButton::Block, training.block_frames > 0),
+        DummyBehavior::Jump => frame.set(InputButton::Up, true),
+    }
+    input.current = frame;
+}
+
+//health comes back once a fighter has not been hit for a while
+fn refill_health(
+    mut fighters: Query<(&mut Health, &mut Refill, Has<HitStun>, Has<BlockStun>, Has<Juggle>, Has<HitStop>)>,
+) {
+    for (mut health, mut refill, in_hitstun, in_blockstun, juggled, in_hitstop) in fighters.iter_mut() {
+        if in_hitstun || in_blockstun || juggled || in_hitstop {
+            refill.0 = REFILL_FRAMES;
+        } else if refill.0 > 0 {
+            refill.0 -= 1;
+            if refill.0 == 0 {
+                health.refill();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(buttons: &[InputButton]) -> InputFrame {
+        let mut frame = InputFrame::default();
+        for button in buttons {
+            frame.set(*button, true);
+        }
+        frame
+    }
+
+    //a world with the fighter of the player and the dummy, stepped one game frame at a time
+    struct Session {
+        world: World,
+        schedule: Schedule,
+        player: Entity,
+        dummy: Entity,
+    }
+
+    impl Session {
+        fn new(dummy_facing: Facing) -> Self {
+            let mut world = World::new();
+            world.init_resource::<Training>();
+            let player = world.spawn((Player, PlayerInput::default())).id();
+            let dummy = world.spawn((Player, Dummy, PlayerInput::default(), dummy_facing)).id();
+            let mut schedule = Schedule::default();
+            schedule.add_systems(dummy_input);
+            Self { world, schedule, player, dummy }
+        }
+
+        fn step(&mut self, player_input: InputFrame) -> InputFrame {
+            self.world.get_mut::<PlayerInput>(self.player).unwrap().current = player_input;
+            self.schedule.run(&mut self.world);
+            self.world.get::<PlayerInput>(self.dummy).unwrap().current
+        }
+
+        fn training(&mut self) -> Mut<'_, Training> {
+            self.world.resource_mut::<Training>()
+        }
+    }
+
+    #[test]
+    fn behaviors_cycle_both_ways() {
+        let mut behavior = DummyBehavior::default();
+        for _ in DummyBehavior::ALL {
+            behavior = behavior.next();
+        }
+        assert_eq!(behavior, DummyBehavior::default());
+        assert_eq!(DummyBehavior::Stand.prev(), DummyBehavior::Jump);
+        assert_eq!(DummyBehavior::Jump.next(), DummyBehavior::Stand);
+        assert_eq!(DummyBehavior::BlockAll.next().prev(), DummyBehavior::BlockAll);
+    }
+
+    #[test]
+    fn slots_cycle_both_ways() {
+        let mut training = Training::default();
+        training.prev_slot();
+        assert_eq!(training.slot, TRAINING_SLOTS - 1);
+        training.next_slot();
+        assert_eq!(training.slot, 0);
+    }
+
+    #[test]
+    fn mirrored_swaps_left_and_right_only() {
+        let both = frame(&[InputButton::Left, InputButton::Right, InputButton::Light]);
+        assert_eq!(mirrored(both), both);
+        assert_eq!(mirrored(frame(&[InputButton::Left, InputButton::Up])), frame(&[InputButton::Right, InputButton::Up]));
+        assert_eq!(mirrored(mirrored(frame(&[InputButton::Right]))), frame(&[InputButton::Right]));
+    }
+
+    #[test]
+    fn recording_goes_to_the_current_slot() {
+        let mut training = Training::default();
+        let recorder = Entity::from_raw(1);
+        training.next_slot();
+        training.toggle_recording(recorder);
+        let DummyMode::Recording { frames, .. } = &mut training.mode else {
+            panic!("not recording");
+        };
+        frames.extend([InputFrame(1), InputFrame(2)]);
+
+        training.toggle_recording(recorder);
+        assert!(matches!(training.mode, DummyMode::Idle));
+        assert_eq!(training.slots[1], [InputFrame(1), InputFrame(2)]);
+        assert!(training.slots[0].is_empty());
+        assert_eq!(training.slot_seconds(1), 2. / MAX_FRAME_RATE as f32);
+    }
+
+    #[test]
+    fn playback_needs_a_recording() {
+        let mut training = Training::default();
+        training.toggle_playback();
+        assert!(matches!(training.mode, DummyMode::Idle));
+
+        training.slots[0] = vec![InputFrame(1)];
+        training.toggle_playback();
+        assert!(matches!(training.mode, DummyMode::Playback { frame: 0 }));
+        training.toggle_playback();
+        assert!(matches!(training.mode, DummyMode::Idle));
+    }
+
+    #[test]
+    fn stop_keeps_what_was_recorded() {
+        let mut training = Training::default();
+        training.toggle_recording(Entity::from_raw(1));
+        if let DummyMode::Recording { frames, .. } = &mut training.mode {
+            frames.push(InputFrame(3));
+        }
+        training.block_frames = 10;
+
+        training.stop();
+        assert!(matches!(training.mode, DummyMode::Idle));
+        assert_eq!(training.slots[0], [InputFrame(3)]);
+        assert_eq!(training.block_frames, 0);
+    }
+
+    #[test]
+    fn the_dummy_plays_back_what_the_player_recorded() {
+        let mut session = Session::new(Facing::Right);
+        let player = session.player;
+        session.training().toggle_recording(player);
+
+        let inputs = [frame(&[InputButton::Right]), frame(&[InputButton::Light]), InputFrame::default()];
+        for input in inputs {
+            //the dummy moves while the fighter of the player stands still
+            assert_eq!(session.step(input), input);
+            assert_eq!(session.world.get::<PlayerInput>(player).unwrap().current, InputFrame::default());
+        }
+        session.training().toggle_recording(player);
+        assert_eq!(session.training().slots[0], inputs);
+
+        session.training().toggle_playback();
+        for input in inputs {
+            assert_eq!(session.step(InputFrame::default()), input);
+        }
+        session.step(InputFrame::default());
+        assert!(matches!(session.training().mode, DummyMode::Idle));
+    }
+
+    #[test]
+    fn playback_turns_around_with_the_dummy() {
+        let mut session = Session::new(Facing::Left);
+        session.training().slots[0] = vec![frame(&[InputButton::Right])];
+        session.training().toggle_playback();
+        assert_eq!(session.step(InputFrame::default()), frame(&[InputButton::Left]));
+    }
+
+    #[test]
+    fn recording_stops_after_ten_seconds() {
+        let mut session = Session::new(Facing::Right);
+        let player = session.player;
+        session.training().toggle_recording(player);
+
+        let input = frame(&[InputButton::Up]);
+        for _ in 0..MAX_RECORDING_FRAMES {
+            session.step(input);
+        }
+        assert!(matches!(session.training().mode, DummyMode::Idle));
+        assert_eq!(session.training().slots[0].len(), MAX_RECORDING_FRAMES);
+        assert_eq!(session.training().slot_seconds(0), 10.);
+
+        //the player gets the fighter back
+        session.step(input);
+        assert_eq!(session.world.get::<PlayerInput>(player).unwrap().current, input);
+    }
+
+    #[test]
+    fn the_dummy_blocks_after_the_first_hit() {
+        let mut session = Session::new(Facing::Right);
+        session.training().behavior = DummyBehavior::BlockAfterFirstHit;
+        assert_eq!(session.step(InputFrame::default()), InputFrame::default());
+
+        let dummy = session.dummy;
+        session.world.entity_mut(dummy).insert(HitStun::new(5));
+        session.step(InputFrame::default());
+        session.world.entity_mut(dummy).remove::<HitStun>();
+        for _ in 0..BLOCK_AFTER_HIT_FRAMES - 1 {
+            assert_eq!(session.step(InputFrame::default()), frame(&[InputButton::Block]));
+        }
+        assert_eq!(session.step(InputFrame::default()), InputFrame::default());
+    }
+}